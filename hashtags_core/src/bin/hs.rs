@@ -1,31 +1,273 @@
 extern crate base64;
 extern crate clap;
+extern crate diff;
 extern crate dirs;
 use chrono::SubsecRound;
 use clap::{App, Arg};
-use hashtags::core::HashTags;
-use serde_json;
+use hashtags::core::{HashTags, QueryOptions};
+use hashtags::error::Error;
+use hashtags::export::{Exporter, Format};
+use hashtags::import::{import_jsonl, import_vault, Split};
+use hashtags::model::{Conflict, Cursor, Note, NoteOrder, Revision, Tag, TagOrder, TimeRange};
+use hashtags::tag::{MarkdownExtractor, RegexExtractor, TagExtractor};
+use hashtags::task::{Project, State, Tasks};
+use hashtags::time::{parse_since, parse_until};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::process;
 use std::string::String;
 
 const SEP_SIMPLE: &str =
     "--------------------------------------------------------------------------------";
 const SEP_EQUAL: &str =
     "================================================================================";
+const PATT_ID: &str = ", ID: ";
 const PATT_HASH: &str = ", Hash: ";
 
+const EXIT_FAILURE: i32 = 1;
+const EXIT_BAD_INPUT: i32 = 2;
+const EXIT_NO_TAGS: i32 = 3;
+const EXIT_DUPLICATE_NOTE: i32 = 4;
+const EXIT_NOTE_NOT_FOUND: i32 = 5;
+const EXIT_AMBIGUOUS_HASH: i32 = 6;
+const EXIT_INVALID_HASH: i32 = 7;
+const EXIT_BAD_FILTER: i32 = 8;
+const EXIT_SQLITE: i32 = 9;
+const EXIT_INVALID_TAG: i32 = 10;
+const EXIT_TAG_NOT_FOUND: i32 = 11;
+const EXIT_TAG_EXISTS: i32 = 12;
+const EXIT_SCHEMA_TOO_NEW: i32 = 13;
+const EXIT_NOT_A_TASK: i32 = 14;
+const EXIT_IO: i32 = 15;
+
+fn exit_with(code: i32, msg: String) -> ! {
+    eprintln!("{}", msg);
+    process::exit(code)
+}
+
+fn exit_on_error<T>(r: Result<T, Error>) -> T {
+    match r {
+        Ok(v) => v,
+        Err(e) => {
+            let code = match e {
+                Error::NoTags(_) => EXIT_NO_TAGS,
+                Error::DuplicateNote => EXIT_DUPLICATE_NOTE,
+                Error::TrashedNote(_) => EXIT_DUPLICATE_NOTE,
+                Error::NoteNotFound(_) => EXIT_NOTE_NOT_FOUND,
+                Error::AmbiguousHash(_, _) => EXIT_AMBIGUOUS_HASH,
+                Error::InvalidHash(_) => EXIT_INVALID_HASH,
+                Error::BadFilter(_) => EXIT_BAD_FILTER,
+                Error::InvalidTag(_) => EXIT_INVALID_TAG,
+                Error::TagNotFound(_) => EXIT_TAG_NOT_FOUND,
+                Error::TagExists(_) => EXIT_TAG_EXISTS,
+                Error::SchemaTooNew(_, _) => EXIT_SCHEMA_TOO_NEW,
+                Error::NotATask(_) => EXIT_NOT_A_TASK,
+                Error::InvalidRecord(_, _) => EXIT_BAD_INPUT,
+                Error::Io(_) => EXIT_IO,
+                Error::Sqlite(_) => EXIT_SQLITE,
+            };
+            exit_with(code, e.to_string())
+        }
+    }
+}
+
 fn get_db_path() -> String {
     let mut home_path = dirs::home_dir().unwrap();
     home_path.push("notes.db");
     String::from(home_path.to_str().unwrap())
 }
 
+// Notes stay in trash for 30 days unless configured otherwise, in the forms
+// accepted by 'parse_since'. They are only purged by 'hs trash purge'.
+fn get_trash_retention() -> String {
+    env::var("HASHTAGS_TRASH_RETENTION").unwrap_or_else(|_| "30d".to_string())
+}
+
+// Tasks are tagged by states in $HASHTAGS_TASK_STATES, 'todo,doing,done' by
+// default, and by projects under $HASHTAGS_PROJECT_TAG, 'project' by default.
+fn get_tasks() -> Result<Tasks, Error> {
+    let mut tasks = match env::var("HASHTAGS_TASK_STATES") {
+        Ok(states) => Tasks::with_states(&states)?,
+        Err(_) => Tasks::default(),
+    };
+    if let Ok(project) = env::var("HASHTAGS_PROJECT_TAG") {
+        tasks.project = project;
+    }
+    Ok(tasks)
+}
+
+fn print_json<T: Serialize>(v: &T) {
+    match serde_json::to_string(v) {
+        Ok(s) => println!("{}", s),
+        Err(e) => exit_with(
+            EXIT_FAILURE,
+            format!("unable to serialize with JSON: {}", e),
+        ),
+    };
+}
+
+// Time created, and the time of the last update if any.
+fn format_times(n: &Note) -> String {
+    let mut meta = n.time_created.trunc_subsecs(0).to_string();
+    if let Some(updated) = n.time_updated {
+        meta.push_str(&format!(", Updated: {}", updated.trunc_subsecs(0)));
+    }
+    meta
+}
+
+fn print_note(n: Note, output: &str) {
+    match output {
+        "simple" => {
+            println!("{}", n.content);
+            println!("{}", SEP_SIMPLE);
+            let mut meta = format_times(&n);
+            if let Some(deleted) = n.time_deleted {
+                meta.push_str(&format!(", Deleted: {}", deleted.trunc_subsecs(0)));
+            }
+            println!("{}, ID: {}, Hash: {}", meta, n.id, base64::encode(n.hash));
+            println!("{}", SEP_EQUAL);
+        }
+        "concise" => {
+            println!("{}", format_times(&n));
+            println!("{}", n.content);
+            println!("{}", SEP_EQUAL);
+        }
+        _ => exit_with(EXIT_BAD_INPUT, format!("unknown output format: {}", output)),
+    };
+}
+
+fn print_notes(notes: Vec<Note>, output: &str) {
+    match output {
+        "json" => print_json(&notes),
+        _ => {
+            for n in notes {
+                print_note(n, output);
+            }
+        }
+    };
+}
+
+fn print_tags(tags: Vec<Tag>, output: &str) {
+    match output {
+        "json" => print_json(&tags),
+        "simple" => {
+            for t in tags {
+                print_tag(&t, &t.display);
+            }
+        }
+        _ => exit_with(EXIT_BAD_INPUT, format!("unknown output format: {}", output)),
+    };
+}
+
+fn print_tag(t: &Tag, label: &str) {
+    let last_used = match t.time_last_used {
+        Some(time) => time.trunc_subsecs(0).to_string(),
+        None => "-".to_string(),
+    };
+    println!("{:>6}  {:<23}  {}", t.count, last_used, label);
+}
+
+// Tags are printed under their parents, indented by their depth, siblings
+// are kept in the order listed.
+fn print_tag_tree(tags: Vec<Tag>) {
+    let mut children = HashMap::<Option<String>, Vec<Tag>>::new();
+    for t in tags {
+        children.entry(t.parent.clone()).or_default().push(t);
+    }
+    fn walk(children: &HashMap<Option<String>, Vec<Tag>>, parent: Option<String>, depth: usize) {
+        for t in children.get(&parent).into_iter().flatten() {
+            let name = t.display.rsplit('/').next().unwrap_or(&t.display);
+            print_tag(t, &format!("{}{}", "  ".repeat(depth), name));
+            walk(children, Some(t.name.clone()), depth + 1);
+        }
+    }
+    walk(&children, None, 0);
+}
+
+fn print_projects(projects: Vec<Project>, tasks: &Tasks, output: &str) {
+    match output {
+        "json" => print_json(&projects),
+        "simple" => {
+            let width = [State::Todo, State::Doing]
+                .iter()
+                .map(|s| tasks.tag(*s).chars().count())
+                .max()
+                .unwrap_or(0);
+            for p in projects {
+                println!("{}", p.name.as_deref().unwrap_or("(no project)"));
+                for t in p.tasks {
+                    let first_line = t.note.content.lines().next().unwrap_or("");
+                    println!(
+                        "  {:<width$}  {}  {}",
+                        tasks.tag(t.state),
+                        t.note.id,
+                        first_line,
+                        width = width
+                    );
+                }
+            }
+        }
+        _ => exit_with(EXIT_BAD_INPUT, format!("unknown output format: {}", output)),
+    };
+}
+
+fn print_revisions(revs: Vec<Revision>, output: &str) {
+    match output {
+        "json" => print_json(&revs),
+        "simple" => {
+            for r in revs {
+                println!(
+                    "{:>4}  {:<23}  {}",
+                    r.number,
+                    r.time_created.trunc_subsecs(0).to_string(),
+                    base64::encode(r.hash)
+                );
+            }
+        }
+        _ => exit_with(EXIT_BAD_INPUT, format!("unknown output format: {}", output)),
+    };
+}
+
+fn print_diff(from: &Revision, to: &Revision) {
+    println!(
+        "--- revision {}, {}",
+        from.number,
+        from.time_created.trunc_subsecs(0)
+    );
+    println!(
+        "+++ revision {}, {}",
+        to.number,
+        to.time_created.trunc_subsecs(0)
+    );
+    for l in diff::lines(&from.content, &to.content) {
+        match l {
+            diff::Result::Left(l) => println!("-{}", l),
+            diff::Result::Both(l, _) => println!(" {}", l),
+            diff::Result::Right(l) => println!("+{}", l),
+        }
+    }
+}
+
 fn main() {
     let matches = App::new("Hashtags App")
+        .arg(
+            Arg::with_name("extractor")
+                .long("extractor")
+                .takes_value(true)
+                .possible_values(&["regex", "markdown"])
+                .default_value("regex")
+                .help("how tags are extracted from notes, 'markdown' skips code"),
+        )
         .subcommand(
             App::new("create").about("create a new note").arg(
                 Arg::with_name("note")
                     .short("n")
                     .takes_value(true)
+                    .required(true)
                     .value_name("note"),
             ),
         )
@@ -36,9 +278,77 @@ fn main() {
                     Arg::with_name("method")
                         .short("m")
                         .takes_value(true)
-                        .possible_values(&["simple"]),
+                        .possible_values(&["simple", "expr"])
+                        .default_value("simple"),
+                )
+                .arg(
+                    Arg::with_name("filter_string")
+                        .short("f")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .help("tags to filter by, '*' matches any characters, ex. proj-*, key-value tags compare by =, !=, <, <=, >, >=, ex. due<2026-11-01"),
+                )
+                .arg(
+                    Arg::with_name("text")
+                        .short("t")
+                        .takes_value(true)
+                        .help("full-text search terms, results are ranked by relevance"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .help("created at or after, ex. 2020-05-01, yesterday, 7d"),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .takes_value(true)
+                        .help("created before, a date includes the whole day"),
+                )
+                .arg(
+                    Arg::with_name("updated_since")
+                        .long("updated-since")
+                        .takes_value(true)
+                        .help("updated, or created if never updated, at or after"),
+                )
+                .arg(
+                    Arg::with_name("updated_until")
+                        .long("updated-until")
+                        .takes_value(true)
+                        .help("updated, or created if never updated, before"),
+                )
+                .arg(
+                    Arg::with_name("sort")
+                        .short("s")
+                        .takes_value(true)
+                        .possible_values(&["created", "updated"])
+                        .help("order by creation or last update, newest first"),
+                )
+                .arg(
+                    Arg::with_name("ascending")
+                        .long("asc")
+                        .requires("sort")
+                        .help("order from the oldest"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .long("limit")
+                        .takes_value(true)
+                        .help("maximum number of notes, JSON output then carries a cursor"),
+                )
+                .arg(
+                    Arg::with_name("offset")
+                        .long("offset")
+                        .takes_value(true)
+                        .help("number of notes to skip"),
+                )
+                .arg(
+                    Arg::with_name("after")
+                        .long("after")
+                        .takes_value(true)
+                        .help("cursor of the last note of the previous page"),
                 )
-                .arg(Arg::with_name("filter_string").short("f").takes_value(true))
                 .arg(
                     Arg::with_name("output_format")
                         .short("o")
@@ -52,55 +362,339 @@ fn main() {
                 Arg::with_name("note")
                     .short("n")
                     .takes_value(true)
+                    .required(true)
                     .value_name("note"),
             ),
         )
+        .subcommand(
+            App::new("show")
+                .about("show a note by its ID or the prefix of its hash")
+                .arg(
+                    Arg::with_name("note")
+                        .help("ID of the note, or prefix of its hash in hex or base64")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("output_format")
+                        .short("o")
+                        .takes_value(true)
+                        .possible_values(&["simple", "json", "concise"])
+                        .default_value("simple"),
+                ),
+        )
+        .subcommand(
+            App::new("log")
+                .about("list revisions of a note, the last one is the current")
+                .arg(
+                    Arg::with_name("note")
+                        .help("ID of the note, or prefix of its hash in hex or base64")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("output_format")
+                        .short("o")
+                        .takes_value(true)
+                        .possible_values(&["simple", "json"])
+                        .default_value("simple"),
+                ),
+        )
+        .subcommand(
+            App::new("diff")
+                .about("show changes between two revisions of a note")
+                .arg(
+                    Arg::with_name("note")
+                        .help("ID of the note, or prefix of its hash in hex or base64")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .help("revision number, defaults to the one before 'to'")
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .help("revision number, defaults to the current one")
+                        .index(3),
+                ),
+        )
+        .subcommand(
+            App::new("delete").about("move a note to trash").arg(
+                Arg::with_name("note")
+                    .help("ID of the note, or prefix of its hash in hex or base64")
+                    .required(true)
+                    .index(1),
+            ),
+        )
+        .subcommand(
+            App::new("todo")
+                .about("list tasks not done yet by projects")
+                .arg(
+                    Arg::with_name("output_format")
+                        .short("o")
+                        .takes_value(true)
+                        .possible_values(&["simple", "json"])
+                        .default_value("simple"),
+                ),
+        )
+        .subcommand(
+            App::new("done").about("mark a task as done").arg(
+                Arg::with_name("note")
+                    .help("ID of the note, or prefix of its hash in hex or base64")
+                    .required(true)
+                    .index(1),
+            ),
+        )
+        .subcommand(
+            App::new("export")
+                .about("write notes out for reading without hs")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["markdown", "jsonl"])
+                        .default_value("markdown")
+                        .help(
+                            "'jsonl' is read back by 'import', with notes in trash \
+                             and revisions",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("method")
+                        .short("m")
+                        .takes_value(true)
+                        .possible_values(&["simple", "expr"])
+                        .default_value("simple"),
+                )
+                .arg(
+                    Arg::with_name("filter_string")
+                        .short("f")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .help("tags to filter by as in 'query', all notes by default"),
+                )
+                .arg(
+                    Arg::with_name("split")
+                        .long("split")
+                        .requires("path")
+                        .help("write one file per note into the directory at 'path'"),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .help("file to write to, standard output by default")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("import")
+                .about("import notes exported in the 'jsonl' format, or a Markdown vault")
+                .arg(
+                    Arg::with_name("vault")
+                        .long("vault")
+                        .requires("path")
+                        .help("import Markdown files under the directory at 'path'"),
+                )
+                .arg(
+                    Arg::with_name("split")
+                        .long("split")
+                        .takes_value(true)
+                        .possible_values(&["file", "heading"])
+                        .default_value("file")
+                        .help("how files in a vault are split into notes"),
+                )
+                .arg(
+                    Arg::with_name("on_conflict")
+                        .long("on-conflict")
+                        .takes_value(true)
+                        .possible_values(&["skip", "replace", "fail"])
+                        .default_value("skip")
+                        .help(
+                            "for notes with the same hash or ID as existing ones, \
+                             'replace' only replaces notes with the same ID",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .help("file to read from, standard input by default")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("trash")
+                .about("manage deleted notes")
+                .subcommand(
+                    App::new("list").about("list notes in trash").arg(
+                        Arg::with_name("output_format")
+                            .short("o")
+                            .takes_value(true)
+                            .possible_values(&["simple", "json", "concise"])
+                            .default_value("simple"),
+                    ),
+                )
+                .subcommand(
+                    App::new("restore").about("restore a note from trash").arg(
+                        Arg::with_name("note")
+                            .help("ID of the note, or prefix of its hash in hex or base64")
+                            .required(true)
+                            .index(1),
+                    ),
+                )
+                .subcommand(
+                    App::new("purge")
+                        .about("permanently remove notes deleted before some time")
+                        .arg(
+                            Arg::with_name("before")
+                                .long("before")
+                                .takes_value(true)
+                                .help("strictly before, defaults to $HASHTAGS_TRASH_RETENTION, or 30d"),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("tags")
+                .about("list tags with usage counts")
+                .arg(
+                    Arg::with_name("sort")
+                        .short("s")
+                        .takes_value(true)
+                        .possible_values(&["name", "count", "recency"])
+                        .default_value("name"),
+                )
+                .arg(
+                    Arg::with_name("output_format")
+                        .short("o")
+                        .takes_value(true)
+                        .possible_values(&["simple", "json"])
+                        .default_value("simple"),
+                )
+                .arg(
+                    Arg::with_name("tree")
+                        .long("tree")
+                        .help("show tags under their parents in the 'simple' output format"),
+                ),
+        )
+        .subcommand(
+            App::new("tag")
+                .about("manage tags across all notes")
+                .subcommand(
+                    App::new("rename")
+                        .about("rename a tag, the new name must not be in use")
+                        .arg(Arg::with_name("from").required(true).index(1))
+                        .arg(Arg::with_name("to").required(true).index(2)),
+                )
+                .subcommand(
+                    App::new("merge")
+                        .about("merge a tag into another one")
+                        .arg(Arg::with_name("from").required(true).index(1))
+                        .arg(Arg::with_name("to").required(true).index(2)),
+                )
+                .subcommand(
+                    App::new("normalize")
+                        .about("normalize tags of all notes in Unicode NFC")
+                        .arg(
+                            Arg::with_name("fold_case")
+                                .long("fold-case")
+                                .help("compare tags case-insensitively from now on"),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("db")
+                .about("manage the database schema")
+                .subcommand(App::new("version").about("show current and latest schema versions"))
+                .subcommand(
+                    App::new("migrate")
+                        .about("bring the schema up to date")
+                        .arg(
+                            Arg::with_name("dry_run")
+                                .long("dry-run")
+                                .help("only list pending migrations"),
+                        ),
+                ),
+        )
         .get_matches();
 
-    let mut hs = HashTags::new(get_db_path().as_str()).unwrap();
+    // The schema is left as it is when managing it.
+    if let Some(m) = matches.subcommand_matches("db") {
+        let mut hs = exit_on_error(HashTags::open(get_db_path().as_str()));
+        match m.subcommand() {
+            ("version", Some(_)) => {
+                let (version, latest) = exit_on_error(hs.schema_version());
+                println!("{}/{}", version, latest);
+            }
+            ("migrate", Some(m)) => {
+                for desc in exit_on_error(hs.migrate(m.is_present("dry_run"))) {
+                    println!("{}", desc);
+                }
+            }
+            _ => exit_with(EXIT_BAD_INPUT, "no db subcommand provided".to_string()),
+        };
+        return;
+    }
+
+    let extractor: Box<dyn TagExtractor> = match matches.value_of("extractor").unwrap() {
+        "markdown" => Box::new(MarkdownExtractor::new()),
+        _ => Box::new(RegexExtractor::new()),
+    };
+    let mut hs = exit_on_error(HashTags::with_extractor(get_db_path().as_str(), extractor));
     if let Some(m) = matches.subcommand_matches("create") {
         let note = m.value_of("note").unwrap();
-        hs.create(note).unwrap();
+        exit_on_error(hs.create(note));
         return;
     }
     if let Some(m) = matches.subcommand_matches("query") {
         let method = m.value_of("method").unwrap();
-        let filter = m.value_of("filter_string").unwrap();
+        let filter = m.value_of("filter_string").unwrap_or("");
         let output = m.value_of("output_format").unwrap();
-        let notes = match hs.query(method, filter) {
-            Ok(n) => n,
-            Err(e) => panic!(format!(
-                "unable to query with ({}, {}), error: {}",
-                method, filter, e
-            )),
+        let range = |since, until| TimeRange {
+            since: m.value_of(since).map(|s| exit_on_error(parse_since(s))),
+            until: m.value_of(until).map(|s| exit_on_error(parse_until(s))),
+        };
+        let order = match (m.value_of("sort"), m.is_present("ascending")) {
+            (Some("created"), false) => Some(NoteOrder::CreatedDesc),
+            (Some("created"), true) => Some(NoteOrder::CreatedAsc),
+            (Some("updated"), false) => Some(NoteOrder::UpdatedDesc),
+            (Some("updated"), true) => Some(NoteOrder::UpdatedAsc),
+            _ => None,
+        };
+        let number = |name| {
+            m.value_of(name).map(|n| match n.parse::<u32>() {
+                Ok(n) => n,
+                Err(e) => exit_with(EXIT_BAD_INPUT, format!("invalid {}: {}", name, e)),
+            })
+        };
+        let opts = QueryOptions {
+            text: m.value_of("text"),
+            created: range("since", "until"),
+            updated: range("updated_since", "updated_until"),
+            order,
+            limit: number("limit"),
+            offset: number("offset"),
+            after: m.value_of("after").map(|c| exit_on_error(Cursor::parse(c))),
+            trashed: false,
         };
-        match output {
-            "json" => {
-                let s = match serde_json::to_string(&notes) {
-                    Ok(s) => s,
-                    Err(e) => panic!(format!("unable to serialize with JSON: {}", e)),
+        // Text output is printed as notes are read, JSON needs them all.
+        if output != "json" {
+            exit_on_error(hs.scan(method, filter, &opts, |n| {
+                print_note(n, output);
+                true
+            }));
+            return;
+        }
+        let notes = exit_on_error(hs.query_with(method, filter, &opts));
+        match (opts.limit, output) {
+            // Paginated JSON output carries the cursor of the next page.
+            (Some(limit), "json") => {
+                let next = match notes.last() {
+                    Some(n) if notes.len() as u32 == limit => Some(n.cursor().to_string()),
+                    _ => None,
                 };
-                println!("{}", s);
-            }
-            "simple" => {
-                for n in notes {
-                    println!("{}", n.content);
-                    println!("{}", SEP_SIMPLE);
-                    println!(
-                        "{}, Hash: {}",
-                        n.time_created.trunc_subsecs(0),
-                        base64::encode(n.hash)
-                    );
-                    println!("{}", SEP_EQUAL);
-                }
-            }
-            "concise" => {
-                for n in notes {
-                    println!("{}", n.content);
-                    println!("{}", SEP_EQUAL);
-                }
+                let page = serde_json::json!({ "notes": notes, "next": next });
+                println!("{}", page);
             }
-            _ => panic!("unknown output format: {}", output),
+            _ => print_notes(notes, output),
         };
         return;
     }
@@ -110,16 +704,180 @@ fn main() {
         if let Some(i) = note.find(SEP_SIMPLE) {
             let real_note = &note[..i - 1]; // trim the EOL
             let meta = &note[i + SEP_SIMPLE.len()..];
-            if let Some(i) = meta.find(PATT_HASH) {
-                let hash = base64::decode(&meta[i + PATT_HASH.len()..]).unwrap();
-                hs.update(real_note, hash).unwrap();
+            // Notes printed before IDs were introduced only carry hashes.
+            let key = match (meta.find(PATT_ID), meta.find(PATT_HASH)) {
+                (Some(i), Some(j)) if i < j => Some(&meta[i + PATT_ID.len()..j]),
+                (_, Some(j)) => Some(&meta[j + PATT_HASH.len()..]),
+                _ => None,
+            };
+            if let Some(key) = key {
+                exit_on_error(hs.update(real_note, key.trim()));
                 return;
             } else {
-                panic!("unable to locate hash in '{}'", meta);
+                exit_with(
+                    EXIT_BAD_INPUT,
+                    format!("unable to locate ID or hash in '{}'", meta),
+                );
             }
         } else {
-            panic!("unable to locate simple_sep in '{}'", note);
+            exit_with(
+                EXIT_BAD_INPUT,
+                format!("unable to locate simple_sep in '{}'", note),
+            );
         }
     }
-    panic!("no subcommand provided");
+    if let Some(m) = matches.subcommand_matches("show") {
+        let key = m.value_of("note").unwrap();
+        let output = m.value_of("output_format").unwrap();
+        let note = exit_on_error(hs.show(key.trim()));
+        print_notes(vec![note], output);
+        return;
+    }
+    if let Some(m) = matches.subcommand_matches("log") {
+        let key = m.value_of("note").unwrap();
+        let output = m.value_of("output_format").unwrap();
+        let revs = exit_on_error(hs.history(key.trim()));
+        print_revisions(revs, output);
+        return;
+    }
+    if let Some(m) = matches.subcommand_matches("diff") {
+        let key = m.value_of("note").unwrap();
+        let revs = exit_on_error(hs.history(key.trim()));
+        let number = |name| {
+            m.value_of(name).map(|s| match s.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= revs.len() => n,
+                _ => exit_with(EXIT_BAD_INPUT, format!("no revision {}", s)),
+            })
+        };
+        let to = number("to").unwrap_or(revs.len());
+        let from = number("from").unwrap_or(if to > 1 { to - 1 } else { to });
+        print_diff(&revs[from - 1], &revs[to - 1]);
+        return;
+    }
+    if let Some(m) = matches.subcommand_matches("delete") {
+        let key = m.value_of("note").unwrap();
+        exit_on_error(hs.delete(key.trim()));
+        return;
+    }
+    if let Some(m) = matches.subcommand_matches("todo") {
+        let tasks = exit_on_error(get_tasks());
+        let output = m.value_of("output_format").unwrap();
+        print_projects(exit_on_error(tasks.open(&hs)), &tasks, output);
+        return;
+    }
+    if let Some(m) = matches.subcommand_matches("done") {
+        let tasks = exit_on_error(get_tasks());
+        let key = m.value_of("note").unwrap();
+        exit_on_error(tasks.set_state(&mut hs, key.trim(), State::Done));
+        return;
+    }
+    if let Some(m) = matches.subcommand_matches("export") {
+        let format = match m.value_of("format").unwrap() {
+            "markdown" => Format::Markdown,
+            "jsonl" => Format::Jsonl,
+            f => exit_with(EXIT_BAD_INPUT, format!("unknown export format: {}", f)),
+        };
+        let method = m.value_of("method").unwrap();
+        let filter = m.value_of("filter_string").unwrap_or("");
+        let e = Exporter::new(&hs, format);
+        let n = match (m.value_of("path"), m.is_present("split")) {
+            (Some(path), true) => exit_on_error(e.write_dir(method, filter, Path::new(path))),
+            (Some(path), false) => {
+                let mut f = exit_on_error(File::create(path).map_err(Error::from));
+                exit_on_error(e.write_to(method, filter, &mut f))
+            }
+            (None, _) => {
+                exit_on_error(e.write_to(method, filter, &mut io::stdout()));
+                return;
+            }
+        };
+        println!("{}", n);
+        return;
+    }
+    if let Some(m) = matches.subcommand_matches("import") {
+        if m.is_present("vault") {
+            let split = match m.value_of("split").unwrap() {
+                "heading" => Split::Heading,
+                _ => Split::File,
+            };
+            let dir = Path::new(m.value_of("path").unwrap());
+            let report = exit_on_error(import_vault(&mut hs, dir, split));
+            println!(
+                "imported: {}, duplicates: {}, untagged: {}",
+                report.imported, report.duplicates, report.untagged
+            );
+            for path in report.skipped {
+                println!("skipped: {}", path.display());
+            }
+            return;
+        }
+        let on_conflict = match m.value_of("on_conflict").unwrap() {
+            "replace" => Conflict::Replace,
+            "fail" => Conflict::Fail,
+            _ => Conflict::Skip,
+        };
+        let report = match m.value_of("path") {
+            Some(path) => {
+                let f = exit_on_error(File::open(path).map_err(Error::from));
+                exit_on_error(import_jsonl(&mut hs, &mut BufReader::new(f), on_conflict))
+            }
+            None => exit_on_error(import_jsonl(&mut hs, &mut io::stdin().lock(), on_conflict)),
+        };
+        println!("imported: {}, skipped: {}", report.imported, report.skipped);
+        return;
+    }
+    if let Some(m) = matches.subcommand_matches("trash") {
+        match m.subcommand() {
+            ("list", Some(m)) => {
+                let output = m.value_of("output_format").unwrap();
+                print_notes(exit_on_error(hs.trash()), output);
+            }
+            ("restore", Some(m)) => {
+                let key = m.value_of("note").unwrap();
+                exit_on_error(hs.restore(key.trim()));
+            }
+            ("purge", Some(m)) => {
+                // Dates stand for the start of the day, notes deleted during
+                // that day are kept.
+                let before = m
+                    .value_of("before")
+                    .map_or_else(get_trash_retention, String::from);
+                let before = exit_on_error(parse_since(&before));
+                println!("{}", exit_on_error(hs.purge(before)));
+            }
+            _ => exit_with(EXIT_BAD_INPUT, "no trash subcommand provided".to_string()),
+        };
+        return;
+    }
+    if let Some(m) = matches.subcommand_matches("tags") {
+        let order = match m.value_of("sort").unwrap() {
+            "count" => TagOrder::Count,
+            "recency" => TagOrder::Recency,
+            _ => TagOrder::Name,
+        };
+        let output = m.value_of("output_format").unwrap();
+        let tags = exit_on_error(hs.list_tags(order));
+        if m.is_present("tree") && output == "simple" {
+            print_tag_tree(tags);
+        } else {
+            print_tags(tags, output);
+        }
+        return;
+    }
+    if let Some(m) = matches.subcommand_matches("tag") {
+        let (merge, m) = match m.subcommand() {
+            ("rename", Some(m)) => (false, m),
+            ("merge", Some(m)) => (true, m),
+            ("normalize", Some(m)) => {
+                exit_on_error(hs.normalize_tags(m.is_present("fold_case")));
+                return;
+            }
+            _ => exit_with(EXIT_BAD_INPUT, "no tag subcommand provided".to_string()),
+        };
+        let from = m.value_of("from").unwrap();
+        let to = m.value_of("to").unwrap();
+        exit_on_error(hs.rename_tag(from, to, merge));
+        return;
+    }
+    exit_with(EXIT_BAD_INPUT, "no subcommand provided".to_string());
 }
@@ -1,39 +1,272 @@
 use super::error::Error;
-use super::model::Note;
+use super::model::{Conflict, Cursor, Note, NoteOrder, Record, Revision, Tag, TagOrder, TimeRange};
 use super::persistence::sqlite::SqlitePersistence;
 use super::persistence::Persistence;
-use super::tag::extract_tags;
+use super::tag::{is_valid_tag, replace_tag_by, Normalizer, RegexExtractor, TagExtractor};
+use super::tokenizer::expr::ExprTokenizer;
 use super::tokenizer::simple::SimpleTokenizer;
-use super::tokenizer::Tokenizer;
+use super::tokenizer::{Expr, Filter, Tokenizer};
+use chrono::{DateTime, Utc};
 use std::boxed::Box;
 use std::vec::Vec;
+use uuid::Uuid;
+
+#[derive(Default)]
+pub struct QueryOptions<'a> {
+    // Full-text search terms on note contents, results are then ranked by
+    // relevance instead of creation time.
+    pub text: Option<&'a str>,
+    pub created: TimeRange,
+    // Bounds on the last update, or creation for notes never updated.
+    pub updated: TimeRange,
+    pub order: Option<NoteOrder>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    // Continue from the cursor of the last note of the previous page, see
+    // 'Note::cursor'.
+    pub after: Option<Cursor>,
+    // Include notes in trash as well.
+    pub trashed: bool,
+}
+
+fn make_filter<'a>(
+    method: &str,
+    filter: &'a str,
+    opts: &QueryOptions<'a>,
+) -> Result<Filter<'a>, Error> {
+    let t: Box<dyn Tokenizer> = match method {
+        "simple" => Box::new(SimpleTokenizer::new()),
+        "expr" => Box::new(ExprTokenizer::new()),
+        ut => return Err(Error::BadFilter(format!("unknown tokenizer: {}", ut))),
+    };
+    // An empty filter doesn't constrain tags.
+    let mut f = if filter.is_empty() {
+        Filter::new(Expr::And(vec![]))
+    } else {
+        t.tokenize(filter)?
+    };
+    f.text = opts.text;
+    f.created = opts.created;
+    f.updated = opts.updated;
+    f.order = opts.order;
+    f.limit = opts.limit;
+    f.offset = opts.offset;
+    f.after = opts.after.clone();
+    f.trashed = opts.trashed;
+    Ok(f)
+}
 
 pub struct HashTags {
     p: Box<dyn Persistence>,
+    e: Box<dyn TagExtractor>,
 }
 
 impl HashTags {
     pub fn new(db_path: &str) -> Result<HashTags, Error> {
+        HashTags::with_extractor(db_path, Box::new(RegexExtractor::new()))
+    }
+
+    // Tags of notes are extracted by 'e' instead of the default regex.
+    pub fn with_extractor(db_path: &str, e: Box<dyn TagExtractor>) -> Result<HashTags, Error> {
         let p = Box::new(SqlitePersistence::new(db_path)?);
-        Ok(HashTags { p })
+        Ok(HashTags { p, e })
+    }
+
+    // Open the database without migrating its schema, for managing the
+    // schema itself.
+    pub fn open(db_path: &str) -> Result<HashTags, Error> {
+        let p = Box::new(SqlitePersistence::open(db_path)?);
+        let e = Box::new(RegexExtractor::new());
+        Ok(HashTags { p, e })
+    }
+
+    // Current and latest versions of the database schema.
+    pub fn schema_version(&self) -> Result<(u32, u32), Error> {
+        Ok((self.p.schema_version()?, self.p.latest_schema_version()))
+    }
+
+    pub fn migrate(&mut self, dry_run: bool) -> Result<Vec<String>, Error> {
+        self.p.migrate(dry_run)
     }
 
     pub fn create(&mut self, note: &str) -> Result<(), Error> {
-        let tags = extract_tags(note)?;
+        let tags = self.e.extract_tags(note)?;
         self.p.create_note(note, tags)
     }
 
     pub fn query(&self, method: &str, filter: &str) -> Result<Vec<Note>, Error> {
-        let t = match method {
-            "simple" => SimpleTokenizer::new(),
-            ut => return Err(Error::GenericError(format!("unknown tokenizer: {}", ut))),
+        self.query_with(method, filter, &QueryOptions::default())
+    }
+
+    pub fn query_with(
+        &self,
+        method: &str,
+        filter: &str,
+        opts: &QueryOptions,
+    ) -> Result<Vec<Note>, Error> {
+        let filter = self.p.normalizer().normalize(filter);
+        self.p.query_notes(&make_filter(method, &filter, opts)?)
+    }
+
+    // Same as 'query_with', but notes are passed to the callback one by one
+    // without being collected, the scan stops when the callback returns false.
+    pub fn scan<F>(
+        &self,
+        method: &str,
+        filter: &str,
+        opts: &QueryOptions,
+        mut f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(Note) -> bool,
+    {
+        let filter = self.p.normalizer().normalize(filter);
+        self.p
+            .scan_notes(&make_filter(method, &filter, opts)?, &mut f)
+    }
+
+    pub fn search(&self, text: &str) -> Result<Vec<Note>, Error> {
+        let opts = QueryOptions {
+            text: Some(text),
+            ..QueryOptions::default()
+        };
+        self.query_with("simple", "", &opts)
+    }
+
+    // Notes could be found either by their IDs, or prefixes of their hashes.
+    // Trashed notes are only found when 'trashed' is set, and vice versa.
+    fn find(&self, key: &str, trashed: bool) -> Result<Note, Error> {
+        let note = match self.p.get_note_by_id(key) {
+            // Keys in the form of IDs are never hash prefixes.
+            Err(Error::NoteNotFound(_)) if Uuid::parse_str(key).is_err() => {
+                self.p.get_note_by_hash_prefix(key)?
+            }
+            r => r?,
         };
-        let f = t.tokenize(filter)?;
-        Ok(self.p.query_notes(&f.ands, &f.ors)?)
+        if note.time_deleted.is_some() != trashed {
+            return Err(Error::NoteNotFound(key.to_string()));
+        }
+        Ok(note)
+    }
+
+    // Restore a note as it is, see 'Persistence::import_note'. Returns
+    // whether the note is imported.
+    pub fn import(&mut self, record: &Record, on_conflict: Conflict) -> Result<bool, Error> {
+        let tags = self.e.extract_tags(&record.note.content)?;
+        self.p.import_note(record, tags, on_conflict)
+    }
+
+    pub fn show(&self, key: &str) -> Result<Note, Error> {
+        self.find(key, false)
+    }
+
+    // Versions of the note from the oldest to the current one.
+    pub fn history(&self, key: &str) -> Result<Vec<Revision>, Error> {
+        let note = self.find(key, false)?;
+        self.p.list_revisions(&note.id)
     }
 
-    pub fn update(&mut self, note: &str, hash: Vec<u8>) -> Result<(), Error> {
-        let tags = extract_tags(note)?;
+    // Same as 'history', but for notes in trash as well, by their IDs.
+    pub fn revisions(&self, id: &str) -> Result<Vec<Revision>, Error> {
+        self.p.list_revisions(id)
+    }
+
+    pub fn update(&mut self, note: &str, key: &str) -> Result<(), Error> {
+        let tags = self.e.extract_tags(note)?;
+        let hash = self.find(key, false)?.hash;
         self.p.update_note_by_hash(&hash, note, tags)
     }
+
+    pub fn delete(&mut self, key: &str) -> Result<(), Error> {
+        let hash = self.find(key, false)?.hash;
+        self.p.delete_note_by_hash(&hash)
+    }
+
+    // Notes in trash, the most recently deleted first.
+    pub fn trash(&self) -> Result<Vec<Note>, Error> {
+        self.p.list_trash()
+    }
+
+    pub fn restore(&mut self, key: &str) -> Result<(), Error> {
+        let hash = self.find(key, true)?.hash;
+        self.p.restore_note_by_hash(&hash)
+    }
+
+    // Purge notes deleted before 'before', returns the number of notes purged.
+    pub fn purge(&mut self, before: DateTime<Utc>) -> Result<usize, Error> {
+        self.p.purge_trash(&before)
+    }
+
+    // Rewrite tag 'from' as 'to' in all notes, returns the number of
+    // notes rewritten. When merging, 'to' is allowed to exist already.
+    pub fn rename_tag(&mut self, from: &str, to: &str, merge: bool) -> Result<usize, Error> {
+        if !is_valid_tag(to) {
+            return Err(Error::InvalidTag(to.to_string()));
+        }
+        let e = &self.e;
+        self.p
+            .rename_tag(from, to, merge, &|content: &str| e.tag_ranges(content))
+    }
+
+    // Tags are compared in Unicode NFC, and case-insensitively with
+    // 'fold_case'. Tags of all notes are extracted again and normalized, for
+    // databases written before or with a different setting.
+    pub fn normalize_tags(&mut self, fold_case: bool) -> Result<(), Error> {
+        let e = &self.e;
+        let extract = |content: &str| -> Vec<String> {
+            match e.extract_tags(content) {
+                Ok(tags) => tags.into_iter().map(String::from).collect(),
+                Err(_) => vec![],
+            }
+        };
+        self.p.normalize_tags(fold_case, &extract)
+    }
+
+    pub fn list_tags(&self, order: TagOrder) -> Result<Vec<Tag>, Error> {
+        self.p.list_tags(&order)
+    }
+
+    // How tags are compared, by the setting of the database.
+    pub fn normalizer(&self) -> Normalizer {
+        self.p.normalizer()
+    }
+
+    // Rewrite tags of a note which 'matches' accepts as 'to', tags are found
+    // by the extractor in use.
+    pub fn replace_tags<F>(&self, content: &str, matches: F, to: &str) -> String
+    where
+        F: Fn(&str) -> bool,
+    {
+        replace_tag_by(content, self.e.tag_ranges(content), matches, to)
+    }
+
+    // Tags of a note normalized as they are stored, none if it fails.
+    pub fn tags_of(&self, content: &str) -> Vec<String> {
+        let n = self.p.normalizer();
+        match self.e.extract_tags(content) {
+            Ok(tags) => tags.into_iter().map(|t| n.normalize(t)).collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::error::Error;
+    use super::HashTags;
+
+    #[test]
+    fn test_find() {
+        let mut hs = HashTags::new(":memory:").unwrap();
+        hs.create("apple #fruit").unwrap();
+        let note = hs.query("simple", "fruit").unwrap().remove(0);
+        assert_eq!(hs.show(&note.id).unwrap().content, "apple #fruit");
+        let prefix = base64::encode(&note.hash);
+        assert_eq!(hs.show(&prefix[..8]).unwrap().id, note.id);
+        // Unknown IDs aren't taken as hash prefixes.
+        assert!(matches!(
+            hs.show("6f2c2a9e-5d7b-4c8e-9a0e-2f1b3c4d5e6f"),
+            Err(Error::NoteNotFound(_))
+        ));
+    }
 }
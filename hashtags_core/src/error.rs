@@ -1,15 +1,84 @@
-use std::string::String;
+use std::error;
 use std::fmt;
+use std::io;
+use std::string::String;
 
 #[derive(Debug)]
 pub enum Error {
-    GenericError(String),
+    NoTags(String),
+    DuplicateNote,
+    // A note with the same content is in trash, by its ID.
+    TrashedNote(String),
+    NoteNotFound(String),
+    AmbiguousHash(String, usize),
+    InvalidHash(String),
+    BadFilter(String),
+    InvalidTag(String),
+    TagNotFound(String),
+    TagExists(String),
+    SchemaTooNew(u32, u32),
+    NotATask(String),
+    InvalidRecord(usize, String),
+    Io(io::Error),
+    Sqlite(rusqlite::Error),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
-            Error::GenericError(ref desc) => write!(f, "{}", desc),
+            Error::NoTags(ref note) => write!(f, "no tags extracted: {}", note),
+            Error::DuplicateNote => write!(f, "note with the same content already exists"),
+            Error::TrashedNote(ref id) => write!(
+                f,
+                "note with the same content is in trash, restore it instead: {}",
+                id
+            ),
+            Error::NoteNotFound(ref key) => write!(f, "no note found by: {}", key),
+            Error::AmbiguousHash(ref prefix, n) => {
+                write!(
+                    f,
+                    "hash prefix '{}' is ambiguous, {} notes matched",
+                    prefix, n
+                )
+            }
+            Error::InvalidHash(ref desc) => write!(f, "invalid hash: {}", desc),
+            Error::BadFilter(ref desc) => write!(f, "bad filter: {}", desc),
+            Error::InvalidTag(ref tag) => write!(f, "invalid tag: '{}'", tag),
+            Error::TagNotFound(ref tag) => write!(f, "no such tag: '{}'", tag),
+            Error::TagExists(ref tag) => write!(f, "tag already exists: '{}'", tag),
+            Error::SchemaTooNew(version, latest) => write!(
+                f,
+                "database schema version {} is newer than the supported {}",
+                version, latest
+            ),
+            Error::NotATask(ref key) => write!(f, "note has no state tag: {}", key),
+            Error::InvalidRecord(line, ref desc) => {
+                write!(f, "invalid record at line {}: {}", line, desc)
+            }
+            Error::Io(ref e) => write!(f, "io: {}", e),
+            Error::Sqlite(ref e) => write!(f, "sqlite: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Sqlite(ref e) => Some(e),
+            _ => None,
         }
     }
 }
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Error {
+        Error::Sqlite(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,203 @@
+use super::core::{HashTags, QueryOptions};
+use super::error::Error;
+use super::model::{Note, Record};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::string::String;
+use std::vec::Vec;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    // Front matter followed by the content of each note.
+    Markdown,
+    // A JSON object per line, which is read back by 'import::import_jsonl'.
+    // Notes in trash are written as well, and each note has its previous
+    // revisions.
+    Jsonl,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+            Format::Jsonl => "jsonl",
+        }
+    }
+}
+
+// Strings in front matter are quoted as JSON, which YAML reads as well.
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+// A note in Markdown with YAML front matter, ex.
+//
+//   ---
+//   id: "9f1c..."
+//   hash: "Vk3e..."
+//   tags: ["project/hashtags", "todo"]
+//   created: "2020-05-01T08:00:00+00:00"
+//   ---
+//
+//   fix the bug #todo #project/hashtags
+//
+// 'updated' is left out for notes never updated.
+pub fn to_markdown(note: &Note, tags: &[String]) -> String {
+    let tags: Vec<String> = tags.iter().map(|t| quote(t)).collect();
+    let mut s = String::from("---\n");
+    s.push_str(&format!("id: {}\n", quote(&note.id)));
+    s.push_str(&format!("hash: {}\n", quote(&base64::encode(&note.hash))));
+    s.push_str(&format!("tags: [{}]\n", tags.join(", ")));
+    s.push_str(&format!(
+        "created: {}\n",
+        quote(&note.time_created.to_rfc3339())
+    ));
+    if let Some(updated) = note.time_updated {
+        s.push_str(&format!("updated: {}\n", quote(&updated.to_rfc3339())));
+    }
+    s.push_str("---\n\n");
+    s.push_str(&note.content);
+    if !note.content.ends_with('\n') {
+        s.push('\n');
+    }
+    s
+}
+
+// Writes notes selected by filters in the forms accepted by 'HashTags::query'.
+pub struct Exporter<'a> {
+    hs: &'a HashTags,
+    format: Format,
+}
+
+impl<'a> Exporter<'a> {
+    pub fn new(hs: &'a HashTags, format: Format) -> Exporter<'a> {
+        Exporter { hs, format }
+    }
+
+    fn render(&self, note: &Note) -> Result<String, Error> {
+        match self.format {
+            Format::Markdown => Ok(to_markdown(note, &self.hs.tags_of(&note.content))),
+            Format::Jsonl => {
+                // The last revision is the content of the note itself.
+                let mut revisions = self.hs.revisions(&note.id)?;
+                revisions.pop();
+                let record = Record {
+                    note: note.clone(),
+                    revisions,
+                };
+                Ok(serde_json::to_string(&record).unwrap() + "\n")
+            }
+        }
+    }
+
+    // Notes are passed to 'f' as they are read, the first error stops it.
+    fn each<F>(&self, method: &str, filter: &str, mut f: F) -> Result<usize, Error>
+    where
+        F: FnMut(&Note) -> Result<(), Error>,
+    {
+        let opts = QueryOptions {
+            trashed: self.format == Format::Jsonl,
+            ..QueryOptions::default()
+        };
+        let mut n = 0;
+        let mut err = None;
+        self.hs.scan(method, filter, &opts, |note| {
+            match f(&note) {
+                Ok(()) => n += 1,
+                Err(e) => err = Some(e),
+            };
+            err.is_none()
+        })?;
+        match err {
+            Some(e) => Err(e),
+            None => Ok(n),
+        }
+    }
+
+    // Write all notes into 'w' one after another, returns the number of notes.
+    pub fn write_to(&self, method: &str, filter: &str, w: &mut dyn Write) -> Result<usize, Error> {
+        let mut w = BufWriter::new(w);
+        let mut first = true;
+        let n = self.each(method, filter, |note| {
+            // Markdown notes are apart by a blank line.
+            if !first && self.format == Format::Markdown {
+                w.write_all(b"\n")?;
+            }
+            first = false;
+            w.write_all(self.render(note)?.as_bytes())?;
+            Ok(())
+        })?;
+        w.flush()?;
+        Ok(n)
+    }
+
+    // Write each note into its own file in 'dir' named by its ID, files of
+    // notes exported before are overwritten. Returns the number of notes.
+    pub fn write_dir(&self, method: &str, filter: &str, dir: &Path) -> Result<usize, Error> {
+        fs::create_dir_all(dir)?;
+        self.each(method, filter, |note| {
+            let path = dir.join(format!("{}.{}", note.id, self.format.extension()));
+            fs::write(path, self.render(note)?)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::core::HashTags;
+    use super::{to_markdown, Exporter, Format};
+    use std::fs;
+
+    #[test]
+    fn test_markdown() {
+        let mut hs = HashTags::new(":memory:").unwrap();
+        hs.create("fix the bug #todo #project/hashtags").unwrap();
+        let note = hs.query("simple", "todo").unwrap().remove(0);
+        let tags = vec!["project/hashtags".to_string(), "todo".to_string()];
+        let md = to_markdown(&note, &tags);
+        let expected = format!(
+            "---\nid: \"{}\"\nhash: \"{}\"\ntags: [\"project/hashtags\", \"todo\"]\n\
+             created: \"{}\"\n---\n\nfix the bug #todo #project/hashtags\n",
+            note.id,
+            base64::encode(&note.hash),
+            note.time_created.to_rfc3339()
+        );
+        assert_eq!(md, expected);
+
+        hs.update("fix the \"bug\"\n#todo:now", &note.id).unwrap();
+        let note = hs.show(&note.id).unwrap();
+        let md = to_markdown(&note, &["todo:now".to_string()]);
+        assert!(md.contains("tags: [\"todo:now\"]\n"));
+        assert!(md.contains(&format!(
+            "updated: \"{}\"\n---\n\nfix the \"bug\"\n#todo:now\n",
+            note.time_updated.unwrap().to_rfc3339()
+        )));
+    }
+
+    #[test]
+    fn test_export() {
+        let mut hs = HashTags::new(":memory:").unwrap();
+        hs.create("apple #fruit").unwrap();
+        hs.create("banana #fruit").unwrap();
+        hs.create("carrot #vegetable").unwrap();
+        let e = Exporter::new(&hs, Format::Markdown);
+
+        let mut out = Vec::<u8>::new();
+        assert_eq!(e.write_to("simple", "fruit", &mut out).unwrap(), 2);
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("\n---\n\n").count(), 2);
+        assert!(out.find("banana").unwrap() < out.find("apple").unwrap());
+        assert!(!out.contains("carrot"));
+
+        let dir = std::env::temp_dir().join(format!("hashtags-export-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(e.write_dir("simple", "", &dir).unwrap(), 3);
+        for note in hs.query("simple", "").unwrap() {
+            let md = fs::read_to_string(dir.join(format!("{}.md", note.id))).unwrap();
+            assert!(md.ends_with(&format!("\n---\n\n{}\n", note.content)));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
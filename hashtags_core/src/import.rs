@@ -0,0 +1,357 @@
+use super::core::HashTags;
+use super::error::Error;
+use super::model::{Conflict, Note, Record};
+use chrono::{DateTime, Utc};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::string::String;
+use std::vec::Vec;
+use walkdir::WalkDir;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub imported: usize,
+    // Notes kept as they are by 'Conflict::Skip'.
+    pub skipped: usize,
+}
+
+// Import notes written by 'export::Format::Jsonl', with their IDs, hashes,
+// times and revisions. Notes deleted go back to trash, blank lines are
+// ignored. Notes before an error are imported already.
+pub fn import_jsonl(
+    hs: &mut HashTags,
+    r: &mut dyn BufRead,
+    on_conflict: Conflict,
+) -> Result<Report, Error> {
+    let mut report = Report::default();
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(e) => return Err(Error::InvalidRecord(i + 1, e.to_string())),
+        };
+        if hs.import(&record, on_conflict)? {
+            report.imported += 1;
+        } else {
+            report.skipped += 1;
+        }
+    }
+    Ok(report)
+}
+
+// How Markdown files in a vault are split into notes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Split {
+    File,
+    // A note from each heading to the next one, text before the first
+    // heading is a note as well.
+    Heading,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct VaultReport {
+    pub imported: usize,
+    // Notes with the same content as existing ones.
+    pub duplicates: usize,
+    // Notes without tags in files with other notes imported.
+    pub untagged: usize,
+    // Files without any tags, or not in UTF-8.
+    pub skipped: Vec<PathBuf>,
+}
+
+fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+// ATX headings, ex. '## Title'. Tags at the start of lines, ex. '#todo',
+// have no space after '#'.
+fn is_heading(line: &str) -> bool {
+    let rest = line.trim_start_matches('#');
+    let level = line.len() - rest.len();
+    (1..=6).contains(&level) && (rest.trim().is_empty() || rest.starts_with(' '))
+}
+
+fn sections(text: &str, split: Split) -> Vec<&str> {
+    if split == Split::File {
+        return vec![text];
+    }
+    let mut starts = vec![0];
+    let mut fenced = false;
+    let mut pos = 0;
+    for line in text.split_inclusive('\n') {
+        if is_fence(line) {
+            fenced = !fenced;
+        } else if !fenced && pos > 0 && is_heading(line) {
+            starts.push(pos);
+        }
+        pos += line.len();
+    }
+    starts.push(text.len());
+    starts.windows(2).map(|w| &text[w[0]..w[1]]).collect()
+}
+
+// Import Markdown files under 'dir', ex. Obsidian or Logseq vaults, with
+// tags inline in the text. Notes are created at the time their files were
+// last modified. Hidden files and directories, ex. '.obsidian', are left
+// out.
+pub fn import_vault(hs: &mut HashTags, dir: &Path, split: Split) -> Result<VaultReport, Error> {
+    let mut report = VaultReport::default();
+    let walker = WalkDir::new(dir)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'));
+    for entry in walker {
+        let entry = entry.map_err(io::Error::from)?;
+        let path = entry.path();
+        if !entry.file_type().is_file() || path.extension() != Some(OsStr::new("md")) {
+            continue;
+        }
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                report.skipped.push(path.to_path_buf());
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let time_created = DateTime::<Utc>::from(fs::metadata(path)?.modified()?);
+        let (mut tagged, mut untagged) = (false, 0);
+        for content in sections(&text, split) {
+            let content = content.trim();
+            if content.is_empty() {
+                continue;
+            }
+            let note = Note {
+                id: String::new(),
+                hash: vec![],
+                content: content.to_string(),
+                time_created,
+                time_updated: None,
+                time_deleted: None,
+                attributes: vec![],
+            };
+            let record = Record {
+                note,
+                revisions: vec![],
+            };
+            match hs.import(&record, Conflict::Skip) {
+                Ok(true) => report.imported += 1,
+                Ok(false) => report.duplicates += 1,
+                Err(Error::NoTags(_)) => {
+                    untagged += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            tagged = true;
+        }
+        if tagged {
+            report.untagged += untagged;
+        } else {
+            report.skipped.push(path.to_path_buf());
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::core::HashTags;
+    use super::super::error::Error;
+    use super::super::export::{Exporter, Format};
+    use super::super::model::Conflict;
+    use super::{import_jsonl, import_vault, sections, Report, Split, VaultReport};
+    use std::fs;
+
+    fn export(hs: &HashTags) -> Vec<u8> {
+        let mut out = Vec::<u8>::new();
+        Exporter::new(hs, Format::Jsonl)
+            .write_to("simple", "", &mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut hs = HashTags::new(":memory:").unwrap();
+        hs.create("apple #fruit #due:2026-11-01").unwrap();
+        hs.create("carrot #vegetable").unwrap();
+        let id = hs.query("simple", "vegetable").unwrap()[0].id.clone();
+        hs.update("carrot #vegetable #orange", &id).unwrap();
+        hs.create("old bread #food").unwrap();
+        let bread = hs.query("simple", "food").unwrap()[0].id.clone();
+        hs.update("stale bread #food", &bread).unwrap();
+        hs.delete(&bread).unwrap();
+        let out = export(&hs);
+
+        let mut restored = HashTags::new(":memory:").unwrap();
+        let report = import_jsonl(&mut restored, &mut &out[..], Conflict::Fail).unwrap();
+        assert_eq!(
+            report,
+            Report {
+                imported: 3,
+                skipped: 0
+            }
+        );
+        let (notes, again) = (
+            hs.query("simple", "").unwrap(),
+            restored.query("simple", "").unwrap(),
+        );
+        assert_eq!(notes.len(), again.len());
+        for (a, b) in notes.iter().zip(again.iter()) {
+            assert_eq!(a.id, b.id);
+            assert_eq!(a.hash, b.hash);
+            assert_eq!(a.content, b.content);
+            assert_eq!(a.time_created, b.time_created);
+            assert_eq!(a.time_updated, b.time_updated);
+            assert_eq!(a.attributes, b.attributes);
+        }
+        assert_eq!(restored.query("simple", "orange").unwrap().len(), 1);
+        let history = |hs: &HashTags, id: &str| -> Vec<(String, String)> {
+            hs.revisions(id)
+                .unwrap()
+                .into_iter()
+                .map(|r| (r.content, r.time_created.to_rfc3339()))
+                .collect()
+        };
+        assert_eq!(history(&restored, &id), history(&hs, &id));
+        assert_eq!(history(&restored, &id).len(), 2);
+
+        // Notes in trash stay there.
+        let (trash, again) = (hs.trash().unwrap(), restored.trash().unwrap());
+        assert_eq!(again.len(), 1);
+        assert_eq!(again[0].id, bread);
+        assert_eq!(again[0].time_deleted, trash[0].time_deleted);
+        assert_eq!(history(&restored, &bread), history(&hs, &bread));
+        assert!(restored.query("simple", "food").unwrap().is_empty());
+        assert_eq!(export(&restored), out);
+    }
+
+    #[test]
+    fn test_conflict() {
+        let mut hs = HashTags::new(":memory:").unwrap();
+        hs.create("apple #fruit").unwrap();
+        let out = export(&hs);
+        let id = hs.query("simple", "fruit").unwrap()[0].id.clone();
+        hs.update("apple pie #fruit", &id).unwrap();
+
+        // The same ID with other content.
+        let r = import_jsonl(&mut hs, &mut &out[..], Conflict::Skip).unwrap();
+        assert_eq!(r.skipped, 1);
+        assert_eq!(hs.show(&id).unwrap().content, "apple pie #fruit");
+        assert!(matches!(
+            import_jsonl(&mut hs, &mut &out[..], Conflict::Fail),
+            Err(Error::DuplicateNote)
+        ));
+        let r = import_jsonl(&mut hs, &mut &out[..], Conflict::Replace).unwrap();
+        assert_eq!(r.imported, 1);
+        assert_eq!(hs.show(&id).unwrap().content, "apple #fruit");
+        assert_eq!(hs.query("simple", "fruit").unwrap().len(), 1);
+
+        // Notes only with the same content are never replaced.
+        let mut other = HashTags::new(":memory:").unwrap();
+        other.create("apple #fruit").unwrap();
+        let out = export(&other);
+        assert!(matches!(
+            import_jsonl(&mut hs, &mut &out[..], Conflict::Replace),
+            Err(Error::DuplicateNote)
+        ));
+        assert_eq!(hs.show(&id).unwrap().content, "apple #fruit");
+        assert_eq!(hs.query("simple", "fruit").unwrap().len(), 1);
+
+        // Records without IDs or hashes get new IDs.
+        let mut hs = HashTags::new(":memory:").unwrap();
+        let line = b"\n{\"content\":\"plum #fruit\",\"time_created\":\"2020-05-01T08:00:00Z\",\
+                     \"time_updated\":null,\"time_deleted\":null}\n";
+        assert_eq!(
+            import_jsonl(&mut hs, &mut &line[..], Conflict::Fail)
+                .unwrap()
+                .imported,
+            1
+        );
+        let note = hs.query("simple", "fruit").unwrap().remove(0);
+        assert!(!note.id.is_empty());
+        assert_eq!(note.time_created.to_rfc3339(), "2020-05-01T08:00:00+00:00");
+
+        let bad = b"{\"content\":\"plum #fruit\",\"hash\":[1,2],\"time_created\":\"2020-05-01T08:00:00Z\",\
+                    \"time_updated\":null,\"time_deleted\":null}";
+        assert!(matches!(
+            import_jsonl(&mut hs, &mut &bad[..], Conflict::Replace),
+            Err(Error::InvalidHash(_))
+        ));
+        assert!(matches!(
+            import_jsonl(&mut hs, &mut &b"\n{\"content\":1}"[..], Conflict::Skip),
+            Err(Error::InvalidRecord(2, _))
+        ));
+    }
+
+    #[test]
+    fn test_sections() {
+        let text = "intro #a\n# One #b\ntext\n```sh\n# not a heading\n```\n#c tag\n## Two\n";
+        assert_eq!(sections(text, Split::File), vec![text]);
+        assert_eq!(
+            sections(text, Split::Heading),
+            vec![
+                "intro #a\n",
+                "# One #b\ntext\n```sh\n# not a heading\n```\n#c tag\n",
+                "## Two\n"
+            ]
+        );
+        assert_eq!(sections("# Only\n", Split::Heading), vec!["# Only\n"]);
+    }
+
+    #[test]
+    fn test_vault() {
+        let dir = std::env::temp_dir().join(format!("hashtags-vault-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("journals")).unwrap();
+        fs::create_dir_all(dir.join(".obsidian")).unwrap();
+        fs::write(dir.join("apple.md"), "apple #fruit\n").unwrap();
+        fs::write(dir.join("plain.md"), "# Plain\nno tags here\n").unwrap();
+        fs::write(dir.join("other.txt"), "other #fruit").unwrap();
+        fs::write(dir.join(".obsidian/app.md"), "hidden #fruit").unwrap();
+        fs::write(dir.join("binary.md"), b"\xff\xfe #fruit").unwrap();
+        fs::write(
+            dir.join("journals/2020_05_01.md"),
+            "# Morning #journal\nrun\n\n# Noon\nlunch\n\n# Night #journal\nsleep\n",
+        )
+        .unwrap();
+
+        let mut hs = HashTags::new(":memory:").unwrap();
+        let report = import_vault(&mut hs, &dir, Split::Heading).unwrap();
+        assert_eq!(
+            report,
+            VaultReport {
+                imported: 3,
+                duplicates: 0,
+                untagged: 1,
+                skipped: vec![dir.join("binary.md"), dir.join("plain.md")],
+            }
+        );
+        let notes = hs.query("simple", "journal").unwrap();
+        assert_eq!(notes.len(), 2);
+        assert!(notes.iter().any(|n| n.content == "# Night #journal\nsleep"));
+        let modified = fs::metadata(dir.join("apple.md"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        let apple = hs.query("simple", "fruit").unwrap().remove(0);
+        assert_eq!(apple.content, "apple #fruit");
+        assert_eq!(
+            apple.time_created,
+            chrono::DateTime::<chrono::Utc>::from(modified)
+        );
+
+        // Whole files are notes of their own, unless the content is there.
+        let report = import_vault(&mut hs, &dir, Split::File).unwrap();
+        assert_eq!((report.imported, report.duplicates), (1, 1));
+        assert_eq!(report.skipped.len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
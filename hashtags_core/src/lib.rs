@@ -1,9 +1,13 @@
 mod persistence;
 mod tokenizer;
-mod model;
-mod tag;
-mod error;
+pub mod model;
+pub mod tag;
+pub mod error;
 pub mod core;
+pub mod task;
+pub mod export;
+pub mod import;
+pub mod time;
 
 extern crate chrono;
 extern crate regex;
@@ -1,12 +1,179 @@
+use super::error::Error;
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::string::String;
 use std::vec::Vec;
 
-#[derive(Serialize)]
+// Notes are referred to by 'id', which never changes, while 'hash' follows
+// the content and is recomputed on every update. Notes read from other
+// sources might leave out 'id', 'hash' and the derived 'attributes'.
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Note {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
     pub hash: Vec<u8>,
     pub content: String,
     pub time_created: DateTime<Utc>,
     pub time_updated: Option<DateTime<Utc>>,
+    pub time_deleted: Option<DateTime<Utc>>,
+    // Key-value tags of the note, ordered by key then value.
+    #[serde(default)]
+    pub attributes: Vec<Attribute>,
+}
+
+// A key-value tag, ex. '#due:2026-11-01', a key might have several values.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Attribute {
+    pub key: String,
+    pub value: String,
+}
+
+impl Note {
+    // Cursor to continue paginating right after this note.
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            time_created: self.time_created,
+            hash: self.hash.clone(),
+        }
+    }
+}
+
+// Position of a note when notes are ordered by creation time, serialized as
+// '<RFC 3339 creation time>/<hash in hex>'.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cursor {
+    pub time_created: DateTime<Utc>,
+    pub hash: Vec<u8>,
+}
+
+impl Cursor {
+    pub fn parse(s: &str) -> Result<Cursor, Error> {
+        let invalid = || Error::BadFilter(format!("invalid cursor: '{}'", s));
+        let mut parts = s.trim().splitn(2, '/');
+        let time_created = match DateTime::parse_from_rfc3339(parts.next().unwrap_or("")) {
+            Ok(t) => t.with_timezone(&Utc),
+            Err(_) => return Err(invalid()),
+        };
+        let hex = parts.next().unwrap_or("");
+        if hex.is_empty() || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let mut hash = Vec::<u8>::with_capacity(hex.len() / 2);
+        for pair in hex.as_bytes().chunks(2) {
+            let pair = std::str::from_utf8(pair).unwrap();
+            match u8::from_str_radix(pair, 16) {
+                Ok(b) if pair.len() == 2 => hash.push(b),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(Cursor { time_created, hash })
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/", self.time_created.to_rfc3339())?;
+        for b in &self.hash {
+            write!(f, "{:02X}", b)?;
+        }
+        Ok(())
+    }
+}
+
+// A version of a note, numbered from 1 in the order they were written, the
+// last revision is the current content of the note. Revisions read from
+// other sources might leave out 'number' and 'hash'.
+#[derive(Deserialize, Serialize)]
+pub struct Revision {
+    #[serde(default)]
+    pub number: u32,
+    #[serde(default)]
+    pub hash: Vec<u8>,
+    pub content: String,
+    pub time_created: DateTime<Utc>,
+}
+
+// A note with its previous revisions, from the oldest, for restoring notes
+// as they are.
+#[derive(Deserialize, Serialize)]
+pub struct Record {
+    #[serde(flatten)]
+    pub note: Note,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub revisions: Vec<Revision>,
+}
+
+#[derive(Serialize)]
+pub struct Tag {
+    pub name: String,
+    pub display: String,
+    pub parent: Option<String>,
+    pub count: u32,
+    pub time_last_used: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Copy)]
+pub enum NoteOrder {
+    CreatedDesc,
+    CreatedAsc,
+    // Notes never updated are ordered by their creation time.
+    UpdatedDesc,
+    UpdatedAsc,
+}
+
+pub enum TagOrder {
+    Name,
+    Count,
+    Recency,
+}
+
+// What to do when importing a note with the same hash or ID as an existing
+// one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Conflict {
+    // Keep the existing note.
+    Skip,
+    // Remove the existing note with the same ID and its revisions for the
+    // imported one. A note with the same content but another ID is never
+    // replaced, that stops with 'Error::DuplicateNote'.
+    Replace,
+    // Stop with 'Error::DuplicateNote'.
+    Fail,
+}
+
+// Bounds of a time range, 'since' is inclusive while 'until' is exclusive.
+#[derive(Clone, Copy, Default)]
+pub struct TimeRange {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::Cursor;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_cursor() {
+        let c = Cursor {
+            time_created: Utc.timestamp_opt(1588291200, 123456789).unwrap(),
+            hash: vec![0x00, 0xAB, 0xFF],
+        };
+        let s = c.to_string();
+        assert_eq!(s, "2020-05-01T00:00:00.123456789+00:00/00ABFF");
+        assert_eq!(Cursor::parse(&s).unwrap(), c);
+        assert_eq!(
+            Cursor::parse("2020-05-01T08:00:00.123456789+08:00/00abff").unwrap(),
+            c
+        );
+        assert!(Cursor::parse("").is_err());
+        assert!(Cursor::parse("2020-05-01T00:00:00Z").is_err());
+        assert!(Cursor::parse("2020-05-01T00:00:00Z/").is_err());
+        assert!(Cursor::parse("2020-05-01T00:00:00Z/ABC").is_err());
+        assert!(Cursor::parse("2020-05-01T00:00:00Z/XY").is_err());
+        assert!(Cursor::parse("2020-05-01T00:00:00Z/台積").is_err());
+        assert!(Cursor::parse("yesterday/00").is_err());
+    }
 }
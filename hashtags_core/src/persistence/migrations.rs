@@ -0,0 +1,516 @@
+use super::super::error::Error;
+use super::super::tag::parent_tag;
+use super::sqlite::{insert_tag, new_note_id};
+use rusqlite::Result as RusqResult;
+use rusqlite::{params, Connection, Transaction};
+use std::result::Result;
+use std::string::String;
+use std::vec::Vec;
+
+// The schema version is kept in 'PRAGMA user_version', it's the number of
+// migrations applied. Databases created before versioning are at version 0,
+// so every step has to cope with a schema that already has its changes.
+pub struct Migration {
+    pub description: &'static str,
+    apply: fn(&Transaction) -> RusqResult<()>,
+}
+
+pub const MIGRATIONS: [Migration; 9] = [
+    Migration {
+        description: "create notes, tags and relations",
+        apply: create_tables,
+    },
+    Migration {
+        description: "index contents of notes for full-text search",
+        apply: create_fts,
+    },
+    Migration {
+        description: "keep revisions of notes",
+        apply: create_revisions,
+    },
+    Migration {
+        description: "give notes stable IDs",
+        apply: add_note_ids,
+    },
+    Migration {
+        description: "move deleted notes to trash",
+        apply: add_trash,
+    },
+    Migration {
+        description: "keep the spelling of tags and add settings",
+        apply: add_tag_display,
+    },
+    Migration {
+        description: "link hierarchical tags to their parents",
+        apply: add_tag_parents,
+    },
+    Migration {
+        description: "keep key-value tags as attributes of notes",
+        apply: add_attributes,
+    },
+    Migration {
+        description: "link revisions to stable IDs of notes",
+        apply: link_revisions_to_ids,
+    },
+];
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+pub fn user_version(conn: &Connection) -> RusqResult<u32> {
+    conn.query_row("PRAGMA user_version", params![], |row| row.get(0))
+}
+
+// Apply pending migrations in order, each in its own transaction, returns
+// descriptions of those applied. Nothing is applied with 'dry_run', the
+// pending ones are returned instead.
+pub fn migrate(conn: &mut Connection, dry_run: bool) -> Result<Vec<String>, Error> {
+    let version = user_version(conn)?;
+    if version > latest_version() {
+        return Err(Error::SchemaTooNew(version, latest_version()));
+    }
+    let mut applied = Vec::<String>::new();
+    for (i, m) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        if !dry_run {
+            let tx = conn.transaction()?;
+            (m.apply)(&tx)?;
+            tx.execute_batch(&format!("PRAGMA user_version = {}", i + 1))?;
+            tx.commit()?;
+        }
+        applied.push(format!("{}: {}", i + 1, m.description));
+    }
+    Ok(applied)
+}
+
+fn column_exists(tx: &Transaction, table: &str, column: &str) -> RusqResult<bool> {
+    tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        params![table, column],
+        |row| row.get(0),
+    )
+}
+
+fn create_tables(tx: &Transaction) -> RusqResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS notes (
+            hash                 BLOB PRIMARY KEY,
+            content              TEXT NOT NULL,
+            time_created         DATETIME NOT NULL,
+            time_updated         DATETIME
+         );
+         CREATE TABLE IF NOT EXISTS tags (
+            name TEXT PRIMARY KEY
+         );
+         CREATE TABLE IF NOT EXISTS relations (
+            tag_name               TEXT,
+            note_hash              BLOB,
+            FOREIGN KEY(tag_name)  REFERENCES tags(name),
+            FOREIGN KEY(note_hash) REFERENCES notes(hash),
+            PRIMARY KEY(tag_name, note_hash)
+         );",
+    )
+}
+
+// Contents of notes are indexed for full-text search, triggers keep the
+// index in sync with the notes table. The index is rebuilt from scratch in
+// case it's already there.
+fn create_fts(tx: &Transaction) -> RusqResult<()> {
+    tx.execute_batch(
+        "DROP TRIGGER IF EXISTS notes_fts_insert;
+         DROP TRIGGER IF EXISTS notes_fts_update;
+         DROP TRIGGER IF EXISTS notes_fts_delete;
+         DROP TABLE IF EXISTS notes_fts;
+         CREATE VIRTUAL TABLE notes_fts USING fts5(hash UNINDEXED, content);
+         INSERT INTO notes_fts (hash, content) SELECT hash, content FROM notes;
+         CREATE TRIGGER notes_fts_insert AFTER INSERT ON notes BEGIN
+            INSERT INTO notes_fts (hash, content) VALUES (new.hash, new.content);
+         END;
+         CREATE TRIGGER notes_fts_update AFTER UPDATE ON notes BEGIN
+            UPDATE notes_fts SET hash = new.hash, content = new.content
+                WHERE hash = old.hash;
+         END;
+         CREATE TRIGGER notes_fts_delete AFTER DELETE ON notes BEGIN
+            DELETE FROM notes_fts WHERE hash = old.hash;
+         END;",
+    )
+}
+
+// Previous contents of notes are kept as revisions, they follow the current
+// hash of the note and go away with it.
+fn create_revisions(tx: &Transaction) -> RusqResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS revisions (
+            id                     INTEGER PRIMARY KEY,
+            note_hash              BLOB NOT NULL,
+            content                TEXT NOT NULL,
+            time_created           DATETIME NOT NULL,
+            FOREIGN KEY(note_hash) REFERENCES notes(hash)
+         );
+         CREATE TRIGGER IF NOT EXISTS revisions_update AFTER UPDATE ON notes
+         WHEN old.content != new.content BEGIN
+            UPDATE revisions SET note_hash = new.hash WHERE note_hash = old.hash;
+            INSERT INTO revisions (note_hash, content, time_created) VALUES
+                (new.hash, old.content, COALESCE(old.time_updated, old.time_created));
+         END;
+         CREATE TRIGGER IF NOT EXISTS revisions_delete AFTER DELETE ON notes BEGIN
+            DELETE FROM revisions WHERE note_hash = old.hash;
+         END;",
+    )
+}
+
+fn add_note_ids(tx: &Transaction) -> RusqResult<()> {
+    if !column_exists(tx, "notes", "id")? {
+        tx.execute("ALTER TABLE notes ADD COLUMN id TEXT", params![])?;
+    }
+    let hashes = {
+        let mut stmt = tx.prepare("SELECT hash FROM notes WHERE id IS NULL")?;
+        let rows = stmt.query_map(params![], |row| row.get::<_, Vec<u8>>(0))?;
+        rows.collect::<RusqResult<Vec<Vec<u8>>>>()?
+    };
+    for hash in hashes {
+        tx.execute(
+            "UPDATE notes SET id = ?1 WHERE hash = ?2",
+            params![new_note_id(), hash],
+        )?;
+    }
+    tx.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS notes_id ON notes(id)",
+        params![],
+    )?;
+    Ok(())
+}
+
+fn add_trash(tx: &Transaction) -> RusqResult<()> {
+    if !column_exists(tx, "notes", "time_deleted")? {
+        tx.execute(
+            "ALTER TABLE notes ADD COLUMN time_deleted DATETIME",
+            params![],
+        )?;
+    }
+    Ok(())
+}
+
+// Tags are keyed by their normalized names, 'display' is the spelling they
+// were first written in, NULL for tags written before.
+fn add_tag_display(tx: &Transaction) -> RusqResult<()> {
+    if !column_exists(tx, "tags", "display")? {
+        tx.execute("ALTER TABLE tags ADD COLUMN display TEXT", params![])?;
+    }
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key   TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+         )",
+        params![],
+    )?;
+    Ok(())
+}
+
+fn add_tag_parents(tx: &Transaction) -> RusqResult<()> {
+    if !column_exists(tx, "tags", "parent")? {
+        tx.execute("ALTER TABLE tags ADD COLUMN parent TEXT", params![])?;
+    }
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS tags_parent ON tags(parent)",
+        params![],
+    )?;
+    let tags = {
+        let mut stmt = tx.prepare(
+            "SELECT name, COALESCE(display, name) FROM tags
+             WHERE parent IS NULL AND instr(name, '/') > 0",
+        )?;
+        let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<RusqResult<Vec<(String, String)>>>()?
+    };
+    for (name, display) in &tags {
+        tx.execute(
+            "UPDATE tags SET parent = ?1 WHERE name = ?2",
+            params![parent_tag(name), name],
+        )?;
+        if let (Some(parent), Some(display)) = (parent_tag(name), parent_tag(display)) {
+            insert_tag(tx, parent, display)?;
+        }
+    }
+    Ok(())
+}
+
+fn add_attributes(tx: &Transaction) -> RusqResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS attributes (
+            note_hash              BLOB,
+            key                    TEXT NOT NULL,
+            value                  TEXT NOT NULL,
+            FOREIGN KEY(note_hash) REFERENCES notes(hash),
+            PRIMARY KEY(note_hash, key, value)
+         );
+         CREATE INDEX IF NOT EXISTS attributes_key ON attributes(key, value);",
+    )?;
+    // Key-value tags used to be plain tags, split them as 'split_attribute'
+    // does, and drop those tags unless they are parents of others.
+    let kv = "instr(tag_name, ':') > 1 AND instr(tag_name, ':') < length(tag_name)";
+    tx.execute(
+        &format!(
+            "INSERT OR IGNORE INTO attributes (note_hash, key, value)
+             SELECT note_hash, substr(tag_name, 1, instr(tag_name, ':') - 1),
+                substr(tag_name, instr(tag_name, ':') + 1)
+             FROM relations WHERE {}",
+            kv
+        ),
+        params![],
+    )?;
+    tx.execute(&format!("DELETE FROM relations WHERE {}", kv), params![])?;
+    tx.execute(
+        &format!(
+            "DELETE FROM tags WHERE {} AND name NOT IN (
+                SELECT parent FROM tags WHERE parent IS NOT NULL)",
+            kv.replace("tag_name", "name")
+        ),
+        params![],
+    )?;
+    Ok(())
+}
+
+// Revisions used to follow the current hash of their notes, they are keyed
+// by the IDs of notes instead, which never change.
+fn link_revisions_to_ids(tx: &Transaction) -> RusqResult<()> {
+    tx.execute_batch(
+        "DROP TRIGGER IF EXISTS revisions_update;
+         DROP TRIGGER IF EXISTS revisions_delete;",
+    )?;
+    if !column_exists(tx, "revisions", "note_id")? {
+        tx.execute_batch(
+            "ALTER TABLE revisions RENAME TO revisions_by_hash;
+             CREATE TABLE revisions (
+                id                     INTEGER PRIMARY KEY,
+                note_id                TEXT NOT NULL,
+                content                TEXT NOT NULL,
+                time_created           DATETIME NOT NULL,
+                FOREIGN KEY(note_id)   REFERENCES notes(id)
+             );
+             INSERT INTO revisions (id, note_id, content, time_created)
+                SELECT revisions_by_hash.id, notes.id, revisions_by_hash.content,
+                    revisions_by_hash.time_created
+                FROM revisions_by_hash JOIN notes ON notes.hash = revisions_by_hash.note_hash;
+             DROP TABLE revisions_by_hash;",
+        )?;
+    }
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS revisions_note ON revisions(note_id);
+         CREATE TRIGGER revisions_update AFTER UPDATE ON notes
+         WHEN old.content != new.content BEGIN
+            INSERT INTO revisions (note_id, content, time_created) VALUES
+                (new.id, old.content, COALESCE(old.time_updated, old.time_created));
+         END;
+         CREATE TRIGGER revisions_delete AFTER DELETE ON notes BEGIN
+            DELETE FROM revisions WHERE note_id = old.id;
+         END;",
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::error::Error;
+    use super::super::super::model::TagOrder;
+    use super::super::super::tokenizer::Expr::{And, Attr};
+    use super::super::super::tokenizer::{Filter, Op};
+    use super::super::sqlite::SqlitePersistence;
+    use super::super::Persistence;
+    use super::{latest_version, migrate, user_version};
+    use rusqlite::{params, Connection};
+    use std::fs;
+
+    fn temp_db(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("hashtags-{}-{}.db", name, std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn count(conn: &Connection, table: &str) -> u32 {
+        conn.query_row(
+            &format!("SELECT COUNT(*) FROM {}", table),
+            params![],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_basic() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert_eq!(ps.schema_version().unwrap(), latest_version());
+        assert!(ps.migrate(true).unwrap().is_empty());
+        assert!(ps.migrate(false).unwrap().is_empty());
+    }
+
+    // Upgrade a database in the schema used before versioning was added.
+    #[test]
+    fn test_upgrade() {
+        let path = temp_db("upgrade");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE notes (
+                    hash                 BLOB PRIMARY KEY,
+                    content              TEXT NOT NULL,
+                    time_created         DATETIME NOT NULL,
+                    time_updated         DATETIME
+                 );
+                 CREATE TABLE tags (
+                    name TEXT PRIMARY KEY
+                 );
+                 CREATE TABLE relations (
+                    tag_name               TEXT,
+                    note_hash              BLOB,
+                    FOREIGN KEY(tag_name)  REFERENCES tags(name), 
+                    FOREIGN KEY(note_hash) REFERENCES notes(hash),
+                    PRIMARY KEY(tag_name, note_hash)
+                 );
+                 INSERT INTO notes VALUES (x'01', 'apple #a', '2020-05-01T00:00:00Z', NULL);
+                 INSERT INTO notes VALUES
+                    (x'02', 'banana #a', '2020-05-02T00:00:00Z', '2020-05-03T00:00:00Z');
+                 INSERT INTO notes VALUES
+                    (x'03', 'cherry #due:2026-11-01', '2020-05-04T00:00:00Z', NULL);
+                 INSERT INTO tags VALUES ('a');
+                 INSERT INTO tags VALUES ('due:2026-11-01');
+                 INSERT INTO relations VALUES ('a', x'01');
+                 INSERT INTO relations VALUES ('a', x'02');
+                 INSERT INTO relations VALUES ('due:2026-11-01', x'03');",
+            )
+            .unwrap();
+        }
+
+        // Nothing is changed by a dry run.
+        let mut ps = SqlitePersistence::open(&path).unwrap();
+        assert_eq!(ps.schema_version().unwrap(), 0);
+        let pending = ps.migrate(true).unwrap();
+        assert_eq!(pending.len() as u32, latest_version());
+        assert_eq!(pending[0], "1: create notes, tags and relations");
+        assert_eq!(ps.schema_version().unwrap(), 0);
+
+        assert_eq!(ps.migrate(false).unwrap(), pending);
+        assert_eq!(ps.schema_version().unwrap(), latest_version());
+        let mut f = Filter::new(And(vec![]));
+        let notes = ps.query_notes(&f).unwrap();
+        assert_eq!(notes.len(), 3);
+        assert!(notes.iter().all(|n| !n.id.is_empty()));
+        assert!(notes.iter().all(|n| n.time_deleted.is_none()));
+        f.text = Some("banana");
+        let notes = ps.query_notes(&f).unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].hash, vec![2u8]);
+
+        // Key-value tags are moved to attributes.
+        let f = Filter::new(Attr("due", Op::Eq, "2026-11-01"));
+        let notes = ps.query_notes(&f).unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].attributes[0].key, "due");
+        assert_eq!(notes[0].attributes[0].value, "2026-11-01");
+        assert!(ps
+            .list_tags(&TagOrder::Name)
+            .unwrap()
+            .iter()
+            .all(|t| t.name == "a"));
+        drop(ps);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Databases created before versioning might already have some of the
+    // changes, migrations should leave them intact.
+    #[test]
+    fn test_upgrade_unversioned() {
+        let path = temp_db("unversioned");
+        {
+            let mut ps = SqlitePersistence::new(&path).unwrap();
+            ps.create_note("apple #a", vec!["a"]).unwrap();
+            let n = ps.query_notes(&Filter::new(And(vec![]))).unwrap().remove(0);
+            ps.update_note_by_hash(&n.hash, "banana #a", vec!["a"])
+                .unwrap();
+        }
+        let mut conn = Connection::open(&path).unwrap();
+        conn.execute_batch("PRAGMA user_version = 0").unwrap();
+        let id: String = conn
+            .query_row("SELECT id FROM notes", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(
+            migrate(&mut conn, false).unwrap().len() as u32,
+            latest_version()
+        );
+        assert_eq!(user_version(&conn).unwrap(), latest_version());
+        assert_eq!(count(&conn, "notes"), 1);
+        assert_eq!(count(&conn, "notes_fts"), 1);
+        assert_eq!(count(&conn, "revisions"), 1);
+        let linked: u32 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM revisions JOIN notes ON notes.id = revisions.note_id",
+                params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(linked, 1);
+        let same_id: String = conn
+            .query_row("SELECT id FROM notes", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(id, same_id);
+        drop(conn);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Revisions keyed by hashes are moved over to the IDs of their notes.
+    #[test]
+    fn test_revisions_by_id() {
+        let path = temp_db("revisions");
+        {
+            let mut ps = SqlitePersistence::new(&path).unwrap();
+            ps.create_note("apple #a", vec!["a"]).unwrap();
+        }
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "DROP TRIGGER revisions_update;
+             DROP TRIGGER revisions_delete;
+             DROP TABLE revisions;
+             CREATE TABLE revisions (
+                id                     INTEGER PRIMARY KEY,
+                note_hash              BLOB NOT NULL,
+                content                TEXT NOT NULL,
+                time_created           DATETIME NOT NULL
+             );
+             INSERT INTO revisions (note_hash, content, time_created)
+                SELECT hash, 'draft #a', time_created FROM notes;
+             INSERT INTO revisions (note_hash, content, time_created)
+                VALUES (x'00', 'orphan #a', '2020-05-01T00:00:00Z');
+             PRAGMA user_version = 8;",
+        )
+        .unwrap();
+        drop(conn);
+
+        let mut ps = SqlitePersistence::open(&path).unwrap();
+        assert_eq!(ps.migrate(false).unwrap().len(), 1);
+        let n = ps.query_notes(&Filter::new(And(vec![]))).unwrap().remove(0);
+        let revs = ps.list_revisions(&n.id).unwrap();
+        let contents: Vec<&str> = revs.iter().map(|r| r.content.as_str()).collect();
+        assert_eq!(contents, vec!["draft #a", "apple #a"]);
+        // Revisions are kept across updates by the new triggers.
+        ps.update_note_by_hash(&n.hash, "banana #a", vec!["a"])
+            .unwrap();
+        assert_eq!(ps.list_revisions(&n.id).unwrap().len(), 3);
+        drop(ps);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_too_new() {
+        let path = temp_db("too-new");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch("PRAGMA user_version = 1000").unwrap();
+        }
+        assert!(matches!(
+            SqlitePersistence::new(&path),
+            Err(Error::SchemaTooNew(1000, _))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+}
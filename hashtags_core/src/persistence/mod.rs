@@ -1,12 +1,62 @@
 use super::error::Error;
 use super::model;
+use super::tag::Normalizer;
+use super::tokenizer::Filter;
+use chrono::{DateTime, Utc};
 use std::result::Result;
 use std::vec::Vec;
 
+mod migrations;
 pub mod sqlite;
 
 pub trait Persistence {
     fn create_note(&mut self, _: &str, _: Vec<&str>) -> Result<(), Error>;
-    fn query_notes(&self, _: &Vec<&str>, _: &Vec<&str>) -> Result<Vec<model::Note>, Error>;
-    fn update_note_by_hash(&mut self, _: &Vec<u8>, _: &str, _: Vec<&str>) -> Result<(), Error>;
+    fn query_notes(&self, _: &Filter) -> Result<Vec<model::Note>, Error>;
+    // Notes are passed to the callback one by one as they are read, and
+    // scanning stops when the callback returns false.
+    fn scan_notes(&self, _: &Filter, _: &mut dyn FnMut(model::Note) -> bool) -> Result<(), Error>;
+    fn get_note_by_id(&self, id: &str) -> Result<model::Note, Error>;
+    fn get_note_by_hash_prefix(&self, _: &str) -> Result<model::Note, Error>;
+    fn update_note_by_hash(&mut self, _: &[u8], _: &str, _: Vec<&str>) -> Result<(), Error>;
+    // Deleted notes are moved to trash, they are left out of queries until
+    // restored, and are gone for good once purged.
+    fn delete_note_by_hash(&mut self, _: &[u8]) -> Result<(), Error>;
+    fn list_trash(&self) -> Result<Vec<model::Note>, Error>;
+    fn restore_note_by_hash(&mut self, _: &[u8]) -> Result<(), Error>;
+    // Purge notes deleted before the given time, returns the number of notes
+    // purged.
+    fn purge_trash(&mut self, _: &DateTime<Utc>) -> Result<usize, Error>;
+    // Insert a note as it is with its ID, times and revisions, for restoring
+    // notes, notes deleted are put in trash. Returns whether the note is
+    // written, which depends on the policy if there is a note with the same
+    // hash or ID.
+    fn import_note(
+        &mut self,
+        _: &model::Record,
+        _: Vec<&str>,
+        _: model::Conflict,
+    ) -> Result<bool, Error>;
+    fn list_revisions(&self, id: &str) -> Result<Vec<model::Revision>, Error>;
+    // Tags are rewritten in contents at byte ranges given by 'ranges'.
+    fn rename_tag(
+        &mut self,
+        _: &str,
+        _: &str,
+        _: bool,
+        ranges: &dyn Fn(&str) -> Vec<(usize, usize)>,
+    ) -> Result<usize, Error>;
+    fn list_tags(&self, _: &model::TagOrder) -> Result<Vec<model::Tag>, Error>;
+    fn normalizer(&self) -> Normalizer;
+    // Save whether to fold case of tags, and rebuild tags of all notes with
+    // the new normalization, tags are extracted from contents by 'extract'.
+    fn normalize_tags(
+        &mut self,
+        fold_case: bool,
+        extract: &dyn Fn(&str) -> Vec<String>,
+    ) -> Result<(), Error>;
+    fn schema_version(&self) -> Result<u32, Error>;
+    fn latest_schema_version(&self) -> u32;
+    // Bring the schema up to date, returns the migrations applied, or those
+    // pending with 'dry_run'.
+    fn migrate(&mut self, dry_run: bool) -> Result<Vec<String>, Error>;
 }
@@ -1,230 +1,853 @@
 use super::super::error::Error;
 use super::super::model;
+use super::super::tag::{parent_tag, replace_tag_by, split_attribute, Normalizer};
+use super::super::tokenizer::{Expr, Filter};
+use super::migrations;
 use super::Persistence;
-use chrono::prelude::Utc;
+use chrono::prelude::{DateTime, Utc};
+use rusqlite::ffi::SQLITE_ERROR;
 use rusqlite::Result as RusqResult;
-use rusqlite::{params, Connection, ToSql, Transaction};
+use rusqlite::{params, Connection, ErrorCode, Row, ToSql, Transaction};
 use sha3::{Digest, Sha3_256};
 use std::result::Result;
 use std::string::String;
 use std::vec::Vec;
+use uuid::Uuid;
+
+const MIN_HASH_PREFIX_LEN: usize = 4;
 
 pub struct SqlitePersistence {
     conn: Connection,
+    n: Normalizer,
 }
 
-fn prepare_notes_query_stmt(and_tags: &Vec<&str>, or_tags: &Vec<&str>) -> Result<String, Error> {
-    if and_tags.len() == 0 && or_tags.len() == 0 {
-        return Err(Error::GenericError("no filter provided".to_string()));
+// Notes tagged with a tag or any of its descendants.
+const SUBTREE_QUERY: &str = "SELECT note_hash FROM relations WHERE tag_name IN (
+    WITH RECURSIVE subtree(name) AS (
+        SELECT ? UNION SELECT tags.name FROM tags JOIN subtree ON tags.parent = subtree.name
+    ) SELECT name FROM subtree)";
+
+// Same as 'SUBTREE_QUERY', for all tags matching a pattern. Patterns are
+// matched by GLOB, which looks up the index of tags for patterns starting
+// with a prefix, like 'proj-*'.
+const PATTERN_QUERY: &str = "SELECT note_hash FROM relations WHERE tag_name IN (
+    WITH RECURSIVE subtree(name) AS (
+        SELECT name FROM tags WHERE name GLOB ?
+        UNION SELECT tags.name FROM tags JOIN subtree ON tags.parent = subtree.name
+    ) SELECT name FROM subtree)";
+
+fn compile_expr<'a>(
+    expr: &'a Expr,
+    stmt: &mut String,
+    params: &mut Vec<&'a dyn ToSql>,
+) -> Result<(), Error> {
+    let (op, exprs) = match expr {
+        Expr::Tag(t) => {
+            stmt.push_str(SUBTREE_QUERY);
+            params.push(t);
+            return Ok(());
+        }
+        Expr::Pattern(p) => {
+            stmt.push_str(PATTERN_QUERY);
+            params.push(p);
+            return Ok(());
+        }
+        Expr::Attr(key, op, value) => {
+            stmt.push_str(&format!(
+                "SELECT note_hash FROM attributes WHERE key = ? AND value {} ?",
+                op.sql()
+            ));
+            params.push(key);
+            params.push(value);
+            return Ok(());
+        }
+        Expr::Not(e) => {
+            stmt.push_str("SELECT hash FROM notes EXCEPT SELECT * FROM (");
+            compile_expr(e, stmt, params)?;
+            stmt.push(')');
+            return Ok(());
+        }
+        Expr::And(exprs) => (" INTERSECT ", exprs),
+        Expr::Or(exprs) => (" UNION ", exprs),
+    };
+    if exprs.is_empty() {
+        return Err(Error::BadFilter("empty sub-expression".to_string()));
     }
-    let mut stmt = String::from("select * from notes where hash in (");
-    let mut add_intersect = false;
-    for _ in and_tags {
-        if add_intersect {
-            stmt.push_str(" INTERSECT ");
+    for (i, e) in exprs.iter().enumerate() {
+        if i > 0 {
+            stmt.push_str(op);
+        }
+        // Compound selects are evaluated from left to right in SQLite,
+        // nested ones have to be wrapped as sub-queries.
+        if let Expr::Tag(_) | Expr::Pattern(_) | Expr::Attr(..) = e {
+            compile_expr(e, stmt, params)?;
+        } else {
+            stmt.push_str("SELECT * FROM (");
+            compile_expr(e, stmt, params)?;
+            stmt.push(')');
         }
-        stmt.push_str("SELECT note_hash FROM relations WHERE tag_name = ?");
-        add_intersect = true;
     }
-    let mut add_union = false;
-    for _ in or_tags {
-        if add_union {
-            stmt.push_str(" UNION ");
-        } else if and_tags.len() > 0 {
-            stmt.push_str(" INTERSECT SELECT * FROM (");
+    Ok(())
+}
+
+fn prepare_notes_query_stmt<'a>(
+    filter: &'a Filter,
+    params: &mut Vec<&'a dyn ToSql>,
+) -> Result<String, Error> {
+    let mut stmt = String::from("SELECT notes.* FROM notes");
+    if filter.text.is_some() {
+        stmt.push_str(" JOIN notes_fts ON notes_fts.hash = notes.hash");
+    }
+    stmt.push_str(" WHERE notes.hash IN (");
+    match &filter.expr {
+        Expr::And(exprs) if exprs.is_empty() => stmt.push_str("SELECT hash FROM notes"),
+        expr => compile_expr(expr, &mut stmt, params)?,
+    };
+    for e in &filter.excludes {
+        stmt.push_str(" EXCEPT SELECT * FROM (");
+        compile_expr(e, &mut stmt, params)?;
+        stmt.push(')');
+    }
+    stmt.push(')');
+    if !filter.trashed {
+        stmt.push_str(" AND notes.time_deleted IS NULL");
+    }
+    let bounds = [
+        ("notes.time_created", &filter.created),
+        (
+            "COALESCE(notes.time_updated, notes.time_created)",
+            &filter.updated,
+        ),
+    ];
+    for (column, range) in bounds.iter() {
+        if let Some(since) = &range.since {
+            stmt.push_str(&format!(" AND julianday({}) >= julianday(?)", column));
+            params.push(since);
+        }
+        if let Some(until) = &range.until {
+            stmt.push_str(&format!(" AND julianday({}) < julianday(?)", column));
+            params.push(until);
         }
-        stmt.push_str("SELECT note_hash FROM relations WHERE tag_name = ?");
-        add_union = true;
     }
-    if and_tags.len() > 0 && or_tags.len() > 0 {
-        stmt.push_str(")");
+    if let Some(text) = &filter.text {
+        stmt.push_str(" AND notes_fts MATCH ?");
+        params.push(text);
     }
-    stmt.push_str(") ORDER BY time_created DESC");
+    // Notes are ranked by relevance first when searching by text.
+    let by_relevance = filter.order.is_none() && filter.text.is_some();
+    let order = filter.order.unwrap_or(model::NoteOrder::CreatedDesc);
+    if let Some(cursor) = &filter.after {
+        let op = match order {
+            model::NoteOrder::CreatedDesc if !by_relevance => "<",
+            model::NoteOrder::CreatedAsc if !by_relevance => ">",
+            _ => {
+                return Err(Error::BadFilter(
+                    "cursors require ordering by creation time".to_string(),
+                ))
+            }
+        };
+        stmt.push_str(&format!(
+            " AND (notes.time_created, notes.hash) {} (?, ?)",
+            op
+        ));
+        params.push(&cursor.time_created);
+        params.push(&cursor.hash);
+    }
+    stmt.push_str(" ORDER BY ");
+    if by_relevance {
+        stmt.push_str("bm25(notes_fts), ");
+    }
+    stmt.push_str(match order {
+        model::NoteOrder::CreatedDesc => "notes.time_created DESC, notes.hash DESC",
+        model::NoteOrder::CreatedAsc => "notes.time_created ASC, notes.hash ASC",
+        model::NoteOrder::UpdatedDesc => {
+            "COALESCE(notes.time_updated, notes.time_created) DESC, notes.hash DESC"
+        }
+        model::NoteOrder::UpdatedAsc => {
+            "COALESCE(notes.time_updated, notes.time_created) ASC, notes.hash ASC"
+        }
+    });
+    match (filter.limit, filter.offset) {
+        (Some(limit), Some(offset)) => {
+            stmt.push_str(&format!(" LIMIT {} OFFSET {}", limit, offset))
+        }
+        (Some(limit), None) => stmt.push_str(&format!(" LIMIT {}", limit)),
+        (None, Some(offset)) => stmt.push_str(&format!(" LIMIT -1 OFFSET {}", offset)),
+        (None, None) => (),
+    };
     Ok(stmt)
 }
 
-fn insert_tags(tx: &Transaction, tags: &Vec<&str>, hash: &Vec<u8>) -> RusqResult<()> {
-    for tag in tags {
-        tx.execute(
-            "INSERT INTO tags (name)
-                SELECT ?1
-                    WHERE NOT EXISTS(
-                        SELECT 1 FROM tags WHERE name = ?2)",
-            params![tag, tag],
+fn note_from_row(row: &Row) -> RusqResult<model::Note> {
+    Ok(model::Note {
+        id: row.get(4)?,
+        time_deleted: row.get(5)?,
+        hash: row.get(0)?,
+        content: row.get(1)?,
+        time_created: row.get(2)?,
+        time_updated: row.get(3)?,
+        attributes: vec![],
+    })
+}
+
+fn load_attributes(conn: &Connection, mut note: model::Note) -> RusqResult<model::Note> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT key, value FROM attributes WHERE note_hash = ?1 ORDER BY key, value",
+    )?;
+    let rows = stmt.query_map(params![note.hash], |row| {
+        Ok(model::Attribute {
+            key: row.get(0)?,
+            value: row.get(1)?,
+        })
+    })?;
+    note.attributes = rows.collect::<RusqResult<Vec<model::Attribute>>>()?;
+    Ok(note)
+}
+
+// A trashed note with the same content is in the way of writing that
+// content again, it has to be restored or purged first.
+fn check_trashed(tx: &Transaction, hash: &[u8]) -> Result<(), Error> {
+    let id = tx.query_row(
+        "SELECT id FROM notes WHERE hash = ?1 AND time_deleted IS NOT NULL",
+        params![hash],
+        |row| row.get(0),
+    );
+    match id {
+        Ok(id) => Err(Error::TrashedNote(id)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(super) fn new_note_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+// Notes are keyed by the hash of their content, a violated constraint on
+// insertion means the same content is already there.
+fn map_duplicate(e: rusqlite::Error) -> Error {
+    match e {
+        rusqlite::Error::SqliteFailure(ref f, _) if f.code == ErrorCode::ConstraintViolation => {
+            Error::DuplicateNote
+        }
+        e => Error::from(e),
+    }
+}
+
+// Malformed full-text search terms are only found out by SQLite when the
+// query runs, which fails with the generic SQLITE_ERROR then.
+fn map_bad_text(filter: &Filter, e: rusqlite::Error) -> Error {
+    match e {
+        rusqlite::Error::SqliteFailure(ref f, Some(ref msg))
+            if filter.text.is_some() && f.extended_code == SQLITE_ERROR =>
+        {
+            Error::BadFilter(format!("bad full-text search terms: {}", msg))
+        }
+        e => Error::from(e),
+    }
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn content_hash(text: &str) -> Vec<u8> {
+    let mut hasher = Sha3_256::new();
+    hasher.input(text);
+    hasher.result().to_vec()
+}
+
+// Tags are inserted along with their ancestors, each linked to its parent.
+pub(super) fn insert_tag(tx: &Transaction, name: &str, display: &str) -> RusqResult<()> {
+    let (mut name, mut display) = (name, display);
+    loop {
+        let parent = parent_tag(name);
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO tags (name, display, parent) VALUES(?1, ?2, ?3)",
+            params![name, display, parent],
         )?;
+        // Ancestors of an existing tag exist already.
+        match (inserted, parent, parent_tag(display)) {
+            (1, Some(p), Some(d)) => {
+                name = p;
+                display = d;
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+// Tags are keyed by their normalized names, and keep the spelling they are
+// first written in. Key-value tags are kept as attributes instead.
+fn insert_tags(tx: &Transaction, tags: &[&str], hash: &[u8], n: &Normalizer) -> RusqResult<()> {
+    for tag in tags {
+        let name = n.normalize(tag);
+        if let Some((key, value)) = split_attribute(&name) {
+            tx.execute(
+                "INSERT OR IGNORE INTO attributes (note_hash, key, value) VALUES(?1, ?2, ?3)",
+                params![hash, key, value],
+            )?;
+            continue;
+        }
+        insert_tag(tx, &name, tag)?;
+        // Different spellings of the same tag in a note are related once.
         tx.execute(
-            "INSERT INTO relations (tag_name, note_hash) VALUES(?1, ?2)",
-            params![tag, hash],
+            "INSERT OR IGNORE INTO relations (tag_name, note_hash) VALUES(?1, ?2)",
+            params![name, hash],
         )?;
     }
     Ok(())
 }
 
 impl SqlitePersistence {
+    // Open the database and bring its schema up to date.
     pub fn new(path: &str) -> Result<SqlitePersistence, Error> {
-        let conn = match Connection::open(path) {
-            Ok(conn) => conn,
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-        };
-        match conn.execute(
-            "CREATE TABLE IF NOT EXISTS notes (
-                hash                 BLOB PRIMARY KEY,
-                content              TEXT NOT NULL,
-                time_created         DATETIME NOT NULL,
-                time_updated         DATETIME
-             )",
-            params![],
-        ) {
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-            _ => (),
-        };
-        match conn.execute(
-            "CREATE TABLE IF NOT EXISTS tags (
-                name TEXT PRIMARY KEY
-             )",
-            params![],
-        ) {
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-            _ => (),
-        };
-        match conn.execute(
-            "CREATE TABLE IF NOT EXISTS relations (
-                tag_name               TEXT,
-                note_hash              BLOB,
-                FOREIGN KEY(tag_name)  REFERENCES tags(name), 
-                FOREIGN KEY(note_hash) REFERENCES notes(hash),
-                PRIMARY KEY(tag_name, note_hash)
-            )",
+        let mut ps = SqlitePersistence::open(path)?;
+        ps.migrate(false)?;
+        let fold_case = ps.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM settings WHERE key = 'fold_case' AND value = 'true')",
             params![],
-        ) {
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-            _ => (),
-        };
+            |row| row.get(0),
+        )?;
+        ps.n = Normalizer::new(fold_case);
+        Ok(ps)
+    }
 
-        Ok(SqlitePersistence { conn })
+    // Open the database as it is, the schema might be out of date.
+    pub fn open(path: &str) -> Result<SqlitePersistence, Error> {
+        let conn = Connection::open(path)?;
+        Ok(SqlitePersistence {
+            conn,
+            n: Normalizer::default(),
+        })
     }
 }
 
 impl Persistence for SqlitePersistence {
     fn create_note(&mut self, text: &str, tags: Vec<&str>) -> Result<(), Error> {
-        let mut hasher = Sha3_256::new();
-        hasher.input(text);
-        let hash = hasher.result();
-        let tx = match self.conn.transaction() {
-            Ok(tx) => tx,
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-        };
-        match tx.execute(
-            "INSERT INTO notes (hash, content, time_created) VALUES(?1, ?2, ?3)",
-            params![hash.as_ref(), text, Utc::now()],
-        ) {
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-            _ => (),
-        };
-        match insert_tags(&tx, &tags, &hash.to_vec()) {
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-            _ => (),
-        }
-        match tx.commit() {
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-            _ => (),
-        };
+        let hash = content_hash(text);
+        let tx = self.conn.transaction()?;
+        check_trashed(&tx, &hash)?;
+        tx.execute(
+            "INSERT INTO notes (hash, content, time_created, id) VALUES(?1, ?2, ?3, ?4)",
+            params![hash, text, Utc::now(), new_note_id()],
+        )
+        .map_err(map_duplicate)?;
+        insert_tags(&tx, &tags, &hash, &self.n)?;
+        tx.commit()?;
         Ok(())
     }
 
-    fn query_notes(
+    fn query_notes(&self, filter: &Filter) -> Result<Vec<model::Note>, Error> {
+        let mut notes = Vec::<model::Note>::new();
+        self.scan_notes(filter, &mut |n| {
+            notes.push(n);
+            true
+        })?;
+        Ok(notes)
+    }
+
+    fn scan_notes(
         &self,
-        and_tags: &Vec<&str>,
-        or_tags: &Vec<&str>,
-    ) -> Result<Vec<model::Note>, Error> {
-        let q = prepare_notes_query_stmt(and_tags, or_tags)?;
+        filter: &Filter,
+        f: &mut dyn FnMut(model::Note) -> bool,
+    ) -> Result<(), Error> {
         let mut params = Vec::<&dyn ToSql>::new();
-        for a in and_tags {
-            params.push(a);
+        let q = prepare_notes_query_stmt(filter, &mut params)?;
+        let mut stmt = self.conn.prepare(&q)?;
+        let note_iter = stmt
+            .query_map(params, note_from_row)
+            .map_err(|e| map_bad_text(filter, e))?;
+        for n in note_iter {
+            let n = n.map_err(|e| map_bad_text(filter, e))?;
+            if !f(load_attributes(&self.conn, n)?) {
+                break;
+            }
         }
-        for o in or_tags {
-            params.push(o);
+        Ok(())
+    }
+
+    fn get_note_by_id(&self, id: &str) -> Result<model::Note, Error> {
+        let r = self.conn.query_row(
+            "SELECT * FROM notes WHERE id = ?1",
+            params![id],
+            note_from_row,
+        );
+        match r {
+            Ok(n) => Ok(load_attributes(&self.conn, n)?),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err(Error::NoteNotFound(id.to_string())),
+            Err(e) => Err(e.into()),
         }
-        let mut stmt = match self.conn.prepare(&q) {
-            Ok(s) => s,
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-        };
-        let note_iter = match stmt.query_map(params, |row| {
-            Ok(model::Note {
-                hash: row.get(0)?,
-                content: row.get(1)?,
-                time_created: row.get(2)?,
-                time_updated: None,
-            })
-        }) {
-            Ok(note_iter) => note_iter,
-            Err(e) => return Err(Error::GenericError(e.to_string())),
+    }
+
+    fn get_note_by_hash_prefix(&self, prefix: &str) -> Result<model::Note, Error> {
+        if prefix.len() < MIN_HASH_PREFIX_LEN {
+            return Err(Error::InvalidHash(format!(
+                "prefix '{}' is shorter than {} characters",
+                prefix, MIN_HASH_PREFIX_LEN
+            )));
+        }
+        let neither =
+            || Error::InvalidHash(format!("'{}' is neither a hex nor a base64 prefix", prefix));
+        // Prefixes are sliced by bytes below, other characters can't be in
+        // either anyway.
+        if !prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ['+', '/', '='].contains(&c))
+        {
+            return Err(neither());
+        }
+        // A prefix is either hex or base64, and it could be both.
+        let hex = if prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            Some(prefix.to_uppercase())
+        } else {
+            None
         };
+        // Only complete base64 quantums could be decoded, the rest is
+        // compared after encoding candidates.
+        let b64 = base64::decode(&prefix[..prefix.len() / 4 * 4]).ok();
+        if hex.is_none() && b64.is_none() {
+            return Err(neither());
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT * FROM notes WHERE hex(hash) LIKE ?1 OR substr(hash, 1, ?2) = ?3")?;
+        let hex_patt = hex.as_ref().map(|h| format!("{}%", h));
+        let b64_len = b64.as_ref().map_or(0, |b| b.len() as i64);
+        let note_iter = stmt.query_map(params![hex_patt, b64_len, b64], note_from_row)?;
         let mut notes = Vec::<model::Note>::new();
         for n in note_iter {
-            match n {
-                Ok(note) => notes.push(note),
-                Err(e) => return Err(Error::GenericError(e.to_string())),
+            let note = n?;
+            let hex_matched = match hex {
+                Some(ref h) => hex_encode(&note.hash).starts_with(h.as_str()),
+                None => false,
+            };
+            if hex_matched || base64::encode(&note.hash).starts_with(prefix) {
+                notes.push(load_attributes(&self.conn, note)?);
             }
         }
-        Ok(notes)
+        match notes.len() {
+            0 => Err(Error::NoteNotFound(prefix.to_string())),
+            1 => Ok(notes.pop().unwrap()),
+            n => Err(Error::AmbiguousHash(prefix.to_string(), n)),
+        }
     }
 
     fn update_note_by_hash(
         &mut self,
-        hash: &Vec<u8>,
+        hash: &[u8],
         text: &str,
         tags: Vec<&str>,
     ) -> Result<(), Error> {
-        let tx = match self.conn.transaction() {
-            Ok(tx) => tx,
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-        };
+        let tx = self.conn.transaction()?;
         // Make sure the note corresponding to that hash exists.
-        let mut hasher = Sha3_256::new();
-        hasher.input(text);
-        let new_hash = hasher.result();
-        match tx.execute(
-            "UPDATE
-                notes
-             SET
-                hash=?1,
-                content=?2,
-                time_updated=?3
-            WHERE
-                hash=?4",
-            params![new_hash.as_ref(), text, Utc::now(), hash],
+        let new_hash = content_hash(text);
+        check_trashed(&tx, &new_hash)?;
+        let updated = tx
+            .execute(
+                "UPDATE
+                    notes
+                 SET
+                    hash=?1,
+                    content=?2,
+                    time_updated=?3
+                WHERE
+                    hash=?4 AND time_deleted IS NULL",
+                params![new_hash, text, Utc::now(), hash],
+            )
+            .map_err(map_duplicate)?;
+        if updated == 0 {
+            return Err(Error::NoteNotFound(base64::encode(hash)));
+        }
+        // Delete all rows in tags
+        tx.execute("DELETE FROM relations WHERE note_hash = ?1", params![hash])?;
+        tx.execute("DELETE FROM attributes WHERE note_hash = ?1", params![hash])?;
+        insert_tags(&tx, &tags, &new_hash, &self.n)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete_note_by_hash(&mut self, hash: &[u8]) -> Result<(), Error> {
+        let deleted = self.conn.execute(
+            "UPDATE notes SET time_deleted = ?1 WHERE hash = ?2 AND time_deleted IS NULL",
+            params![Utc::now(), hash],
+        )?;
+        if deleted == 0 {
+            return Err(Error::NoteNotFound(base64::encode(hash)));
+        }
+        Ok(())
+    }
+
+    fn list_trash(&self) -> Result<Vec<model::Note>, Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM notes WHERE time_deleted IS NOT NULL
+             ORDER BY time_deleted DESC, hash DESC",
+        )?;
+        let note_iter = stmt.query_map(params![], note_from_row)?;
+        let mut notes = Vec::<model::Note>::new();
+        for n in note_iter {
+            notes.push(load_attributes(&self.conn, n?)?);
+        }
+        Ok(notes)
+    }
+
+    fn restore_note_by_hash(&mut self, hash: &[u8]) -> Result<(), Error> {
+        let restored = self.conn.execute(
+            "UPDATE notes SET time_deleted = NULL WHERE hash = ?1 AND time_deleted IS NOT NULL",
+            params![hash],
+        )?;
+        if restored == 0 {
+            return Err(Error::NoteNotFound(base64::encode(hash)));
+        }
+        Ok(())
+    }
+
+    fn purge_trash(&mut self, before: &DateTime<Utc>) -> Result<usize, Error> {
+        let tx = self.conn.transaction()?;
+        let expired = "SELECT hash FROM notes
+             WHERE time_deleted IS NOT NULL AND julianday(time_deleted) < julianday(?1)";
+        for table in &["relations", "attributes"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE note_hash IN ({})", table, expired),
+                params![before],
+            )?;
+        }
+        let purged = tx.execute(
+            &format!("DELETE FROM notes WHERE hash IN ({})", expired),
+            params![before],
+        )?;
+        tx.commit()?;
+        Ok(purged)
+    }
+
+    fn import_note(
+        &mut self,
+        record: &model::Record,
+        tags: Vec<&str>,
+        on_conflict: model::Conflict,
+    ) -> Result<bool, Error> {
+        let note = &record.note;
+        let hash = content_hash(&note.content);
+        if !note.hash.is_empty() && note.hash != hash {
+            return Err(Error::InvalidHash(format!(
+                "'{}' doesn't match the content of note '{}'",
+                base64::encode(&note.hash),
+                note.id
+            )));
+        }
+        let id = if note.id.is_empty() {
+            new_note_id()
+        } else {
+            note.id.clone()
+        };
+        let tx = self.conn.transaction()?;
+        // The note with the same content, if any, has to be the one with the
+        // same ID to be replaced.
+        let same_hash = match tx.query_row(
+            "SELECT id FROM notes WHERE hash = ?1",
+            params![hash],
+            |row| row.get::<_, String>(0),
         ) {
-            Ok(updated) => {
-                if updated == 0 {
-                    return Err(Error::GenericError(
-                        "unable to locate row by hash".to_string(),
-                    ));
+            Ok(other) => Some(other),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let same_id =
+            match tx.query_row("SELECT hash FROM notes WHERE id = ?1", params![id], |row| {
+                row.get::<_, Vec<u8>>(0)
+            }) {
+                Ok(other) => Some(other),
+                Err(rusqlite::Error::QueryReturnedNoRows) => None,
+                Err(e) => return Err(e.into()),
+            };
+        if same_hash.is_some() || same_id.is_some() {
+            match on_conflict {
+                model::Conflict::Skip => return Ok(false),
+                model::Conflict::Fail => return Err(Error::DuplicateNote),
+                model::Conflict::Replace => {
+                    if same_hash.is_some_and(|other| other != id) {
+                        return Err(Error::DuplicateNote);
+                    }
+                    if let Some(old_hash) = same_id {
+                        for table in &["relations", "attributes"] {
+                            tx.execute(
+                                &format!("DELETE FROM {} WHERE note_hash = ?1", table),
+                                params![old_hash],
+                            )?;
+                        }
+                        tx.execute("DELETE FROM notes WHERE id = ?1", params![id])?;
+                    }
                 }
             }
-            Err(e) => return Err(Error::GenericError(e.to_string())),
+        }
+        tx.execute(
+            "INSERT INTO notes (hash, content, time_created, time_updated, time_deleted, id)
+             VALUES(?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                hash,
+                note.content,
+                note.time_created,
+                note.time_updated,
+                note.time_deleted,
+                id
+            ],
+        )?;
+        for revision in &record.revisions {
+            tx.execute(
+                "INSERT INTO revisions (note_id, content, time_created) VALUES(?1, ?2, ?3)",
+                params![id, revision.content, revision.time_created],
+            )?;
+        }
+        insert_tags(&tx, &tags, &hash, &self.n)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn list_revisions(&self, id: &str) -> Result<Vec<model::Revision>, Error> {
+        let current = self.conn.query_row(
+            "SELECT content, COALESCE(time_updated, time_created) FROM notes WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        );
+        let current = match current {
+            Ok(c) => c,
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                return Err(Error::NoteNotFound(id.to_string()))
+            }
+            Err(e) => return Err(e.into()),
         };
-        // Delete all rows in tags
-        match tx.execute("DELETE FROM relations WHERE note_hash = ?1", params![hash]) {
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-            _ => (),
+        let mut stmt = self.conn.prepare(
+            "SELECT content, time_created FROM revisions WHERE note_id = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut versions = Vec::new();
+        for r in rows {
+            versions.push(r?);
+        }
+        versions.push(current);
+        Ok(versions
+            .into_iter()
+            .enumerate()
+            .map(
+                |(i, (content, time_created)): (usize, (String, _))| model::Revision {
+                    number: i as u32 + 1,
+                    hash: content_hash(&content),
+                    content,
+                    time_created,
+                },
+            )
+            .collect())
+    }
+
+    fn rename_tag(
+        &mut self,
+        from: &str,
+        to: &str,
+        merge: bool,
+        ranges: &dyn Fn(&str) -> Vec<(usize, usize)>,
+    ) -> Result<usize, Error> {
+        let n = self.n;
+        let (from, display) = (&n.normalize(from), to);
+        let to = &n.normalize(to);
+        let tx = self.conn.transaction()?;
+        let tag_exists = |name: &str| -> RusqResult<bool> {
+            tx.query_row(
+                "SELECT EXISTS(SELECT 1 FROM tags WHERE name = ?1)",
+                params![name],
+                |row| row.get(0),
+            )
         };
-        match insert_tags(&tx, &tags, &new_hash.to_vec()) {
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-            _ => (),
+        if !tag_exists(from)? {
+            return Err(Error::TagNotFound(from.to_string()));
+        }
+        if from == to || (!merge && tag_exists(to)?) {
+            return Err(Error::TagExists(to.to_string()));
+        }
+        let notes = {
+            let mut stmt = tx.prepare(
+                "SELECT hash, content FROM notes WHERE hash IN (
+                    SELECT note_hash FROM relations WHERE tag_name = ?1)",
+            )?;
+            let note_iter = stmt.query_map(params![from], |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, String>(1)?))
+            })?;
+            let mut notes = Vec::<(Vec<u8>, String)>::new();
+            for n in note_iter {
+                notes.push(n?);
+            }
+            notes
         };
-        match tx.commit() {
-            Err(e) => return Err(Error::GenericError(e.to_string())),
-            _ => (),
+        insert_tag(&tx, to, display)?;
+        for (hash, content) in &notes {
+            let text = replace_tag_by(
+                content,
+                ranges(content),
+                |t| &n.normalize(t) == from,
+                display,
+            );
+            let new_hash = content_hash(&text);
+            tx.execute(
+                "UPDATE notes SET hash=?1, content=?2, time_updated=?3 WHERE hash=?4",
+                params![new_hash, text, Utc::now(), hash],
+            )
+            .map_err(map_duplicate)?;
+            tx.execute(
+                "UPDATE relations SET note_hash = ?1 WHERE note_hash = ?2 AND tag_name != ?3",
+                params![new_hash, hash, from],
+            )?;
+            tx.execute(
+                "UPDATE attributes SET note_hash = ?1 WHERE note_hash = ?2",
+                params![new_hash, hash],
+            )?;
+            tx.execute(
+                "DELETE FROM relations WHERE note_hash = ?1 AND tag_name = ?2",
+                params![hash, from],
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO relations (tag_name, note_hash) VALUES(?1, ?2)",
+                params![to, new_hash],
+            )?;
+        }
+        // Tags with descendants are kept as their ancestor.
+        tx.execute(
+            "DELETE FROM tags WHERE name = ?1 AND NOT EXISTS(SELECT 1 FROM tags WHERE parent = ?1)",
+            params![from],
+        )?;
+        tx.commit()?;
+        Ok(notes.len())
+    }
+
+    fn normalizer(&self) -> Normalizer {
+        self.n
+    }
+
+    fn normalize_tags(
+        &mut self,
+        fold_case: bool,
+        extract: &dyn Fn(&str) -> Vec<String>,
+    ) -> Result<(), Error> {
+        let n = Normalizer::new(fold_case);
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES('fold_case', ?1)",
+            params![fold_case.to_string()],
+        )?;
+        let notes = {
+            let mut stmt = tx.prepare("SELECT hash, content FROM notes")?;
+            let note_iter = stmt.query_map(params![], |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, String>(1)?))
+            })?;
+            note_iter.collect::<RusqResult<Vec<(Vec<u8>, String)>>>()?
         };
+        tx.execute("DELETE FROM relations", params![])?;
+        tx.execute("DELETE FROM attributes", params![])?;
+        for (hash, content) in &notes {
+            let tags = extract(content);
+            let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+            insert_tags(&tx, &tags, hash, &n)?;
+        }
+        // Tags no longer used are removed, unless they're ancestors of used ones.
+        tx.execute(
+            "DELETE FROM tags WHERE name NOT IN (
+                WITH RECURSIVE used(name) AS (
+                    SELECT tag_name FROM relations UNION
+                    SELECT tags.parent FROM tags JOIN used ON tags.name = used.name
+                ) SELECT name FROM used WHERE name IS NOT NULL)",
+            params![],
+        )?;
+        tx.commit()?;
+        self.n = n;
         Ok(())
     }
+
+    fn schema_version(&self) -> Result<u32, Error> {
+        Ok(migrations::user_version(&self.conn)?)
+    }
+
+    fn latest_schema_version(&self) -> u32 {
+        migrations::latest_version()
+    }
+
+    fn migrate(&mut self, dry_run: bool) -> Result<Vec<String>, Error> {
+        migrations::migrate(&mut self.conn, dry_run)
+    }
+
+    fn list_tags(&self, order: &model::TagOrder) -> Result<Vec<model::Tag>, Error> {
+        let order_by = match order {
+            model::TagOrder::Name => "name ASC",
+            model::TagOrder::Count => "count DESC, name ASC",
+            model::TagOrder::Recency => "time_last_used DESC, name ASC",
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT
+                tags.name AS name,
+                COALESCE(tags.display, tags.name) AS display,
+                tags.parent AS parent,
+                COUNT(notes.hash) AS count,
+                MAX(COALESCE(notes.time_updated, notes.time_created)) AS time_last_used
+             FROM
+                tags
+                LEFT JOIN relations ON relations.tag_name = tags.name
+                LEFT JOIN notes ON notes.hash = relations.note_hash
+                    AND notes.time_deleted IS NULL
+             GROUP BY tags.name
+             ORDER BY {}",
+            order_by
+        ))?;
+        let tag_iter = stmt.query_map(params![], |row| {
+            Ok(model::Tag {
+                name: row.get(0)?,
+                display: row.get(1)?,
+                parent: row.get(2)?,
+                count: row.get(3)?,
+                time_last_used: row.get(4)?,
+            })
+        })?;
+        let mut tags = Vec::<model::Tag>::new();
+        for t in tag_iter {
+            tags.push(t?);
+        }
+        Ok(tags)
+    }
 }
 
 #[cfg(test)]
+// Assertions of the original tests are kept as they were written.
+#[allow(clippy::len_zero, clippy::nonminimal_bool)]
 mod test {
+    use super::super::super::error::Error;
+    use super::super::super::model::{Cursor, Note, NoteOrder, TagOrder, TimeRange};
+    use super::super::super::tag::{extract_tags, MarkdownExtractor, RegexExtractor, TagExtractor};
+    use super::super::super::tokenizer::expr::ExprTokenizer;
+    use super::super::super::tokenizer::simple::SimpleTokenizer;
+    use super::super::super::tokenizer::Expr::And;
+    use super::super::super::tokenizer::{Filter, Tokenizer};
     use super::Persistence;
-    use super::SqlitePersistence;
+    use super::{new_note_id, SqlitePersistence};
+    use chrono::prelude::{TimeZone, Utc};
+    use rusqlite::params;
+
+    fn query(ps: &SqlitePersistence, q: &str) -> Vec<Note> {
+        ps.query_notes(&SimpleTokenizer::new().tokenize(q).unwrap())
+            .unwrap()
+    }
+
+    fn ranges(content: &str) -> Vec<(usize, usize)> {
+        RegexExtractor::new().tag_ranges(content)
+    }
+
+    fn search(ps: &SqlitePersistence, q: &str, text: &str) -> Vec<String> {
+        let mut f = SimpleTokenizer::new().tokenize(q).unwrap();
+        if q.is_empty() {
+            f.expr = And(vec![]);
+        }
+        f.text = Some(text);
+        ps.query_notes(&f)
+            .unwrap()
+            .into_iter()
+            .map(|n| n.content)
+            .collect()
+    }
 
     #[test]
     fn test_basic() {
@@ -233,34 +856,173 @@ mod test {
             .create_note("content-1", vec!["tag-1", "tag-2", "tag-3", "tag-4"])
             .is_err());
         // Inserted content should be able to be queried.
-        let notes = ps.query_notes(&vec!["tag-1"], &vec![]).unwrap();
+        let notes = query(&ps, "tag-1");
         assert!(notes.len() == 1 && notes[0].content == "content-1");
-        let notes = ps
-            .query_notes(&vec!["tag-1", "tag-2", "tag-3"], &vec![])
-            .unwrap();
+        let notes = query(&ps, "tag-1,tag-2,tag-3");
         assert!(notes.len() == 1 && notes[0].content == "content-1");
-        let notes = ps
-            .query_notes(&vec!["tag-1", "tag-2", "tag-5"], &vec![])
-            .unwrap();
+        let notes = query(&ps, "tag-1,tag-2,tag-5");
         assert!(notes.len() == 0);
         // Duplicate content should be rejected.
-        assert!(ps.create_note("content-1", vec![]).is_err());
+        assert!(matches!(
+            ps.create_note("content-1", vec![]),
+            Err(Error::DuplicateNote)
+        ));
         // Build more complex scenario.
         assert!(!ps
             .create_note("content-2", vec!["tag-1", "tag-3", "tag-6"])
             .is_err());
         assert!(!ps.create_note("content-3", vec!["tag-3", "tag-6"]).is_err());
         // Test AND and OR.
-        let notes = ps
-            .query_notes(&vec!["tag-1", "tag-3"], &vec!["tag-4", "tag-6"])
-            .unwrap();
+        let notes = query(&ps, "tag-1,tag-3,tag-4|tag-6");
         assert!(
             notes.len() == 2 && notes[0].content == "content-2" && notes[1].content == "content-1"
         );
-        let notes = ps
-            .query_notes(&vec!["tag-1", "tag-3"], &vec!["tag-6"])
-            .unwrap();
+        let notes = query(&ps, "tag-1,tag-3,tag-6");
+        assert!(notes.len() == 1 && notes[0].content == "content-2");
+    }
+
+    #[test]
+    fn test_expr() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps.create_note("content-1", vec!["work", "rust"]).is_ok());
+        assert!(ps.create_note("content-2", vec!["home", "chores"]).is_ok());
+        assert!(ps.create_note("content-3", vec!["home", "rust"]).is_ok());
+        assert!(ps.create_note("content-4", vec!["work", "go"]).is_ok());
+        let t = ExprTokenizer::new();
+        let query = |q| ps.query_notes(&t.tokenize(q).unwrap()).unwrap();
+        let notes = query("(work & rust) | (home & !chores)");
+        assert!(
+            notes.len() == 2 && notes[0].content == "content-3" && notes[1].content == "content-1"
+        );
+        let notes = query("!home");
+        assert!(
+            notes.len() == 2 && notes[0].content == "content-4" && notes[1].content == "content-1"
+        );
+        let notes = query("!(rust | go) & (home | work)");
+        assert!(notes.len() == 1 && notes[0].content == "content-2");
+        let notes = query("!!chores");
         assert!(notes.len() == 1 && notes[0].content == "content-2");
+        let notes = query("rust & !(work & !go)");
+        assert!(notes.len() == 1 && notes[0].content == "content-3");
+    }
+
+    #[test]
+    fn test_exclude() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps.create_note("content-1", vec!["work", "done"]).is_ok());
+        assert!(ps.create_note("content-2", vec!["work"]).is_ok());
+        assert!(ps.create_note("content-3", vec!["home", "done"]).is_ok());
+        assert!(ps.create_note("content-4", vec!["work", "urgent"]).is_ok());
+        let notes = query(&ps, "work,-done");
+        assert!(
+            notes.len() == 2 && notes[0].content == "content-4" && notes[1].content == "content-2"
+        );
+        let notes = query(&ps, "work,!done,!urgent");
+        assert!(notes.len() == 1 && notes[0].content == "content-2");
+        let notes = query(&ps, "-done,home|urgent");
+        assert!(notes.len() == 1 && notes[0].content == "content-4");
+        let notes = query(&ps, "-work");
+        assert!(notes.len() == 1 && notes[0].content == "content-3");
+        let notes = query(&ps, "home|-work");
+        assert!(notes.len() == 1 && notes[0].content == "content-3");
+        let notes = query(&ps, "work,urgent|-done");
+        assert!(
+            notes.len() == 2 && notes[0].content == "content-4" && notes[1].content == "content-2"
+        );
+    }
+
+    #[test]
+    fn test_pattern() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps.create_note("content-1", vec!["proj-a", "2018"]).is_ok());
+        assert!(ps
+            .create_note("content-2", vec!["proj-b/x", "q1-2018"])
+            .is_ok());
+        assert!(ps.create_note("content-3", vec!["proj", "2019"]).is_ok());
+        let contents =
+            |q| -> Vec<String> { query(&ps, q).into_iter().map(|n| n.content).collect() };
+        assert_eq!(contents("proj-*"), vec!["content-2", "content-1"]);
+        assert_eq!(contents("*2018"), vec!["content-2", "content-1"]);
+        assert_eq!(contents("proj*,-*2018"), vec!["content-3"]);
+        assert_eq!(contents("2019|proj-b"), vec!["content-3", "content-2"]);
+        assert!(contents("*-c").is_empty());
+        let f = ExprTokenizer::new().tokenize("proj* & !proj-*").unwrap();
+        assert_eq!(ps.query_notes(&f).unwrap().len(), 1);
+
+        // Patterns starting with a prefix are looked up by the index.
+        let plan: Vec<String> = ps
+            .conn
+            .prepare("EXPLAIN QUERY PLAN SELECT name FROM tags WHERE name GLOB ?")
+            .unwrap()
+            .query_map(params!["proj-*"], |row| row.get(3))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert!(plan.iter().any(|p| p.starts_with("SEARCH")), "{:?}", plan);
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        let notes = [
+            (
+                "a #due:2026-10-20 #priority:high",
+                vec!["due:2026-10-20", "priority:high"],
+            ),
+            (
+                "b #due:2026-11-01 #priority:low",
+                vec!["due:2026-11-01", "priority:low"],
+            ),
+            (
+                "c #due:2026-12-24 #at:10:30",
+                vec!["due:2026-12-24", "at:10:30"],
+            ),
+            ("d #work", vec!["work"]),
+        ];
+        for (content, tags) in notes.iter() {
+            ps.create_note(content, tags.clone()).unwrap();
+        }
+        let contents = |ps: &SqlitePersistence, q| -> Vec<String> {
+            query(ps, q)
+                .into_iter()
+                .map(|n| n.content[..1].to_string())
+                .collect()
+        };
+        assert_eq!(contents(&ps, "due<2026-11-01"), vec!["a"]);
+        assert_eq!(contents(&ps, "due<=2026-11-01"), vec!["b", "a"]);
+        assert_eq!(contents(&ps, "due>2026-11-01"), vec!["c"]);
+        assert_eq!(contents(&ps, "priority=high"), vec!["a"]);
+        assert_eq!(contents(&ps, "priority:low"), vec!["b"]);
+        assert_eq!(contents(&ps, "priority!=high"), vec!["b"]);
+        assert_eq!(contents(&ps, "at=10:30"), vec!["c"]);
+        assert_eq!(contents(&ps, "-priority=high"), vec!["d", "c", "b"]);
+        assert_eq!(contents(&ps, "due>=2026-11-01|work"), vec!["d", "c", "b"]);
+        let f = ExprTokenizer::new()
+            .tokenize("due<2026-12-01 & !priority!=high")
+            .unwrap();
+        assert_eq!(ps.query_notes(&f).unwrap().len(), 1);
+
+        // Key-value tags aren't plain tags.
+        assert!(contents(&ps, "due").is_empty());
+        let tags = ps.list_tags(&TagOrder::Name).unwrap();
+        assert!(tags.len() == 1 && tags[0].name == "work");
+
+        let n = ps.get_note_by_hash_prefix(&base64::encode(&query(&ps, "at:10:30")[0].hash));
+        let attrs: Vec<(String, String)> = n
+            .unwrap()
+            .attributes
+            .into_iter()
+            .map(|a| (a.key, a.value))
+            .collect();
+        let attr = |k: &str, v: &str| (k.to_string(), v.to_string());
+        assert_eq!(attrs, vec![attr("at", "10:30"), attr("due", "2026-12-24")]);
+
+        // Attributes follow updates of notes.
+        let hash = query(&ps, "priority=low")[0].hash.clone();
+        ps.update_note_by_hash(&hash, "b #priority:high", vec!["priority:high"])
+            .unwrap();
+        assert_eq!(contents(&ps, "priority=high"), vec!["b", "a"]);
+        assert!(contents(&ps, "due=2026-11-01").is_empty());
     }
 
     #[test]
@@ -269,14 +1031,791 @@ mod test {
         assert!(!ps
             .create_note("content-1", vec!["tag-1", "tag-2", "tag-3", "tag-4"])
             .is_err());
-        let notes = ps.query_notes(&vec!["tag-1"], &vec![]).unwrap();
+        let notes = query(&ps, "tag-1");
         assert!(notes.len() == 1 && notes[0].content == "content-1");
         ps.update_note_by_hash(&notes[0].hash, "content-2", vec!["tag-1", "tag-2"])
             .unwrap();
-        let notes = ps.query_notes(&vec!["tag-1"], &vec![]).unwrap();
+        // Updating a note which doesn't exist should fail.
+        assert!(matches!(
+            ps.update_note_by_hash(&notes[0].hash, "content-3", vec!["tag-1"]),
+            Err(Error::NoteNotFound(_))
+        ));
+        // Updating to content of another note should fail.
+        assert!(ps.create_note("content-3", vec!["tag-3"]).is_ok());
+        let notes = query(&ps, "tag-1");
+        assert!(matches!(
+            ps.update_note_by_hash(&notes[0].hash, "content-3", vec!["tag-1"]),
+            Err(Error::DuplicateNote)
+        ));
+        let notes = query(&ps, "tag-1");
         assert!(notes.len() == 1 && notes[0].content == "content-2")
     }
 
+    #[test]
+    fn test_delete_basic() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps.create_note("content-1", vec!["tag-1", "tag-2"]).is_ok());
+        assert!(ps.create_note("content-2", vec!["tag-1"]).is_ok());
+        let notes = query(&ps, "tag-2");
+        assert!(notes.len() == 1 && notes[0].content == "content-1");
+        ps.delete_note_by_hash(&notes[0].hash).unwrap();
+        assert!(query(&ps, "tag-2").is_empty());
+        let notes = query(&ps, "tag-1");
+        assert!(notes.len() == 1 && notes[0].content == "content-2");
+        // Relations are kept in trash for the note to be restored.
+        let relations: i64 = ps
+            .conn
+            .query_row("SELECT COUNT(*) FROM relations", params![], |r| r.get(0))
+            .unwrap();
+        assert_eq!(relations, 3);
+        // Deleting an unknown hash should fail.
+        assert!(matches!(
+            ps.delete_note_by_hash(&[0u8; 32]),
+            Err(Error::NoteNotFound(_))
+        ));
+        // So does deleting a note already in trash.
+        let trashed = ps.list_trash().unwrap();
+        assert!(matches!(
+            ps.delete_note_by_hash(&trashed[0].hash),
+            Err(Error::NoteNotFound(_))
+        ));
+        // The same content could be created again once purged.
+        ps.delete_note_by_hash(&notes[0].hash).unwrap();
+        assert!(matches!(
+            ps.create_note("content-2", vec!["tag-1"]),
+            Err(Error::TrashedNote(_))
+        ));
+        ps.purge_trash(&(Utc::now() + chrono::Duration::seconds(1)))
+            .unwrap();
+        assert!(ps.create_note("content-2", vec!["tag-1"]).is_ok());
+    }
+
+    #[test]
+    fn test_trash() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps.create_note("content-1 #a", vec!["a"]).is_ok());
+        assert!(ps.create_note("content-2 #a", vec!["a"]).is_ok());
+        assert!(ps.create_note("content-3 #a", vec!["a"]).is_ok());
+        let notes = query(&ps, "a");
+        ps.delete_note_by_hash(&notes[0].hash).unwrap();
+        ps.delete_note_by_hash(&notes[2].hash).unwrap();
+        let contents: Vec<String> = query(&ps, "a").into_iter().map(|n| n.content).collect();
+        assert_eq!(contents, vec!["content-2 #a"]);
+        assert_eq!(ps.list_tags(&TagOrder::Name).unwrap()[0].count, 1);
+        let trashed = ps.list_trash().unwrap();
+        let contents: Vec<&str> = trashed.iter().map(|n| n.content.as_str()).collect();
+        assert_eq!(contents, vec!["content-1 #a", "content-3 #a"]);
+        assert!(trashed.iter().all(|n| n.time_deleted.is_some()));
+        // Trashed notes could still be found, but not updated.
+        let n = ps.get_note_by_id(&trashed[0].id).unwrap();
+        assert!(n.time_deleted.is_some());
+        assert!(matches!(
+            ps.update_note_by_hash(&n.hash, "content-4 #a", vec!["a"]),
+            Err(Error::NoteNotFound(_))
+        ));
+
+        // Restored notes come back with their tags.
+        ps.restore_note_by_hash(&trashed[0].hash).unwrap();
+        assert!(matches!(
+            ps.restore_note_by_hash(&trashed[0].hash),
+            Err(Error::NoteNotFound(_))
+        ));
+        let n = query(&ps, "a")
+            .into_iter()
+            .find(|n| n.id == trashed[0].id)
+            .unwrap();
+        assert!(n.time_deleted.is_none());
+        assert_eq!(ps.list_trash().unwrap().len(), 1);
+
+        // Only notes deleted before the given time are purged.
+        let deleted = ps.list_trash().unwrap()[0].time_deleted.unwrap();
+        assert_eq!(ps.purge_trash(&deleted).unwrap(), 0);
+        assert_eq!(
+            ps.purge_trash(&(deleted + chrono::Duration::seconds(1)))
+                .unwrap(),
+            1
+        );
+        assert!(ps.list_trash().unwrap().is_empty());
+        let relations: i64 = ps
+            .conn
+            .query_row("SELECT COUNT(*) FROM relations", params![], |r| r.get(0))
+            .unwrap();
+        assert_eq!(relations, 2);
+
+        // The content of a trashed note can't be written again, the trashed
+        // note is kept as it is.
+        let n = query(&ps, "a").remove(0);
+        ps.delete_note_by_hash(&n.hash).unwrap();
+        assert!(matches!(
+            ps.create_note(&n.content, vec!["a"]),
+            Err(Error::TrashedNote(ref id)) if id == &n.id
+        ));
+        assert!(ps.create_note("other #a", vec!["a"]).is_ok());
+        let other = query(&ps, "a").remove(0);
+        assert!(matches!(
+            ps.update_note_by_hash(&other.hash, &n.content, vec!["a"]),
+            Err(Error::TrashedNote(_))
+        ));
+        assert_eq!(ps.list_trash().unwrap().len(), 1);
+        assert!(ps.restore_note_by_hash(&n.hash).is_ok());
+        assert_eq!(query(&ps, "a").len(), 3);
+    }
+
+    #[test]
+    fn test_hash_prefix() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        for i in 0..64 {
+            let content = format!("content-{}", i);
+            assert!(ps.create_note(&content, vec!["tag-1"]).is_ok());
+        }
+        let notes = query(&ps, "tag-1");
+        for n in &notes {
+            let b64 = base64::encode(&n.hash);
+            let hex = super::hex_encode(&n.hash);
+            let found = ps.get_note_by_hash_prefix(&b64).unwrap();
+            assert_eq!(found.content, n.content);
+            let found = ps.get_note_by_hash_prefix(&b64[..12]).unwrap();
+            assert_eq!(found.content, n.content);
+            let found = ps.get_note_by_hash_prefix(&hex[..12]).unwrap();
+            assert_eq!(found.content, n.content);
+            let found = ps
+                .get_note_by_hash_prefix(&hex[..13].to_lowercase())
+                .unwrap();
+            assert_eq!(found.content, n.content);
+        }
+        // Too short, invalid, unknown or ambiguous prefixes should be rejected.
+        assert!(matches!(
+            ps.get_note_by_hash_prefix("abc"),
+            Err(Error::InvalidHash(_))
+        ));
+        assert!(matches!(
+            ps.get_note_by_hash_prefix("!!!!!"),
+            Err(Error::InvalidHash(_))
+        ));
+        assert!(matches!(
+            ps.get_note_by_hash_prefix("台積電x"),
+            Err(Error::InvalidHash(_))
+        ));
+        assert!(matches!(
+            ps.get_note_by_hash_prefix("AAAAAAAAAAAAAAAA"),
+            Err(Error::NoteNotFound(_))
+        ));
+        for (i, content) in ["content-a", "content-b"].iter().enumerate() {
+            let mut hash = vec![0xAB, 0xCD, 0xEF, i as u8];
+            hash.resize(32, 0);
+            ps.conn
+                .execute(
+                    "INSERT INTO notes (hash, content, time_created, id) VALUES(?1, ?2, ?3, ?4)",
+                    params![hash, content, Utc::now(), new_note_id()],
+                )
+                .unwrap();
+        }
+        assert!(matches!(
+            ps.get_note_by_hash_prefix("abcdef"),
+            Err(Error::AmbiguousHash(_, 2))
+        ));
+        let found = ps.get_note_by_hash_prefix("abcdef01").unwrap();
+        assert_eq!(found.content, "content-b");
+    }
+
+    #[test]
+    fn test_full_text() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps
+            .create_note("deploy the cluster #work #k8s", vec!["work", "k8s"])
+            .is_ok());
+        assert!(ps
+            .create_note("cluster cluster headache #work", vec!["work"])
+            .is_ok());
+        assert!(ps
+            .create_note("fix the sink, then deploy #home", vec!["home"])
+            .is_ok());
+        // Results are ranked by relevance.
+        assert_eq!(
+            search(&ps, "", "cluster"),
+            vec![
+                "cluster cluster headache #work",
+                "deploy the cluster #work #k8s"
+            ]
+        );
+        assert_eq!(search(&ps, "", "deploy").len(), 2);
+        assert_eq!(
+            search(&ps, "work", "deploy"),
+            vec!["deploy the cluster #work #k8s"]
+        );
+        assert_eq!(
+            search(&ps, "-k8s", "deploy"),
+            vec!["fix the sink, then deploy #home"]
+        );
+        assert_eq!(
+            search(&ps, "", "deploy AND sink"),
+            vec!["fix the sink, then deploy #home"]
+        );
+        assert!(search(&ps, "", "kubernetes").is_empty());
+        // Malformed terms are bad filters.
+        for text in &["deploy(", "AND", "\"deploy", "nosuch:deploy"] {
+            let mut f = Filter::new(And(vec![]));
+            f.text = Some(text);
+            assert!(matches!(ps.query_notes(&f), Err(Error::BadFilter(_))));
+        }
+        // The index follows updates and deletions.
+        let notes = query(&ps, "home");
+        ps.update_note_by_hash(&notes[0].hash, "fix the roof #home", vec!["home"])
+            .unwrap();
+        assert!(search(&ps, "", "sink").is_empty());
+        assert_eq!(search(&ps, "", "roof"), vec!["fix the roof #home"]);
+        let notes = query(&ps, "home");
+        ps.delete_note_by_hash(&notes[0].hash).unwrap();
+        assert!(search(&ps, "", "roof").is_empty());
+        ps.rename_tag("k8s", "kubernetes", false, &ranges).unwrap();
+        assert_eq!(
+            search(&ps, "", "kubernetes"),
+            vec!["deploy the cluster #work #kubernetes"]
+        );
+    }
+
+    #[test]
+    fn test_time_range() {
+        let ps = SqlitePersistence::new(":memory:").unwrap();
+        for (i, d) in [1, 3, 5].iter().enumerate() {
+            let time = Utc.with_ymd_and_hms(2020, 5, *d, 0, 0, 0).unwrap();
+            ps.conn
+                .execute(
+                    "INSERT INTO notes (hash, content, time_created, id) VALUES(?1, ?2, ?3, ?4)",
+                    params![
+                        vec![i as u8; 32],
+                        format!("content-{}", d),
+                        time,
+                        new_note_id()
+                    ],
+                )
+                .unwrap();
+        }
+        let updated = Utc.with_ymd_and_hms(2020, 5, 6, 0, 0, 0).unwrap();
+        ps.conn
+            .execute(
+                "UPDATE notes SET time_updated = ?1 WHERE content = 'content-1'",
+                params![updated],
+            )
+            .unwrap();
+        let at = |d| Some(Utc.with_ymd_and_hms(2020, 5, d, 0, 0, 0).unwrap());
+        let contents = |created: TimeRange, updated: TimeRange| {
+            let mut f = Filter::new(And(vec![]));
+            f.created = created;
+            f.updated = updated;
+            ps.query_notes(&f)
+                .unwrap()
+                .into_iter()
+                .map(|n| n.content)
+                .collect::<Vec<String>>()
+        };
+        let range = |since, until| TimeRange { since, until };
+        assert_eq!(
+            contents(range(at(3), None), TimeRange::default()),
+            vec!["content-5", "content-3"]
+        );
+        assert_eq!(
+            contents(range(None, at(3)), TimeRange::default()),
+            vec!["content-1"]
+        );
+        assert_eq!(
+            contents(range(at(2), at(4)), TimeRange::default()),
+            vec!["content-3"]
+        );
+        assert!(contents(range(at(4), at(2)), TimeRange::default()).is_empty());
+        // Notes never updated are bounded by their creation time.
+        assert_eq!(
+            contents(TimeRange::default(), range(at(4), None)),
+            vec!["content-5", "content-1"]
+        );
+        assert_eq!(
+            contents(range(None, at(2)), range(at(6), at(7))),
+            vec!["content-1"]
+        );
+    }
+
+    #[test]
+    fn test_order() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        for c in ["content-1", "content-2", "content-3"].iter() {
+            assert!(ps.create_note(c, vec!["tag-1"]).is_ok());
+        }
+        let notes = query(&ps, "tag-1");
+        assert!(notes.iter().all(|n| n.time_updated.is_none()));
+        ps.update_note_by_hash(&notes[2].hash, "content-4", vec!["tag-1"])
+            .unwrap();
+        let contents = |order| {
+            let mut f = SimpleTokenizer::new().tokenize("tag-1").unwrap();
+            f.order = order;
+            ps.query_notes(&f)
+                .unwrap()
+                .into_iter()
+                .map(|n| n.content)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(contents(None), vec!["content-3", "content-2", "content-4"]);
+        assert_eq!(
+            contents(Some(NoteOrder::CreatedAsc)),
+            vec!["content-4", "content-2", "content-3"]
+        );
+        assert_eq!(
+            contents(Some(NoteOrder::UpdatedDesc)),
+            vec!["content-4", "content-3", "content-2"]
+        );
+        assert_eq!(
+            contents(Some(NoteOrder::UpdatedAsc)),
+            vec!["content-2", "content-3", "content-4"]
+        );
+        let notes = query(&ps, "tag-1");
+        assert!(notes[2].time_updated.unwrap() > notes[2].time_created);
+        assert!(notes[0].time_updated.is_none());
+    }
+
+    #[test]
+    fn test_pagination() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        let time = Utc.with_ymd_and_hms(2020, 5, 1, 0, 0, 0).unwrap();
+        // Notes created at the same time are ordered by hash.
+        for i in 0..10u8 {
+            ps.conn
+                .execute(
+                    "INSERT INTO notes (hash, content, time_created, id) VALUES(?1, ?2, ?3, ?4)",
+                    params![vec![i; 32], format!("content-{}", i), time, new_note_id()],
+                )
+                .unwrap();
+        }
+        assert!(ps.create_note("content-10", vec!["tag-1"]).is_ok());
+        let page = |order, limit, offset, after: Option<Cursor>| {
+            let mut f = Filter::new(And(vec![]));
+            f.order = order;
+            f.limit = limit;
+            f.offset = offset;
+            f.after = after;
+            ps.query_notes(&f).unwrap()
+        };
+        let contents = |notes: &Vec<Note>| {
+            notes
+                .iter()
+                .map(|n| n.content.as_str())
+                .collect::<Vec<&str>>()
+                .join(",")
+        };
+        let notes = page(None, Some(3), None, None);
+        assert_eq!(contents(&notes), "content-10,content-9,content-8");
+        let notes = page(None, Some(3), Some(2), None);
+        assert_eq!(contents(&notes), "content-8,content-7,content-6");
+        let notes = page(None, None, Some(9), None);
+        assert_eq!(contents(&notes), "content-1,content-0");
+        // Walk through all notes by cursor.
+        let mut after = None;
+        let mut all = Vec::<String>::new();
+        loop {
+            let notes = page(None, Some(4), None, after);
+            all.push(contents(&notes));
+            if notes.len() < 4 {
+                break;
+            }
+            after = Some(notes.last().unwrap().cursor());
+        }
+        assert_eq!(
+            all,
+            vec![
+                "content-10,content-9,content-8,content-7",
+                "content-6,content-5,content-4,content-3",
+                "content-2,content-1,content-0"
+            ]
+        );
+        let cursor = Cursor::parse(&page(None, Some(2), None, None)[1].cursor().to_string());
+        let notes = page(Some(NoteOrder::CreatedAsc), Some(2), None, cursor.ok());
+        assert_eq!(contents(&notes), "content-10");
+        let cursor = page(Some(NoteOrder::CreatedAsc), Some(2), None, None)[1].cursor();
+        let notes = page(Some(NoteOrder::CreatedAsc), Some(2), None, Some(cursor));
+        assert_eq!(contents(&notes), "content-2,content-3");
+        // Cursors only work with ordering by creation time.
+        let mut f = Filter::new(And(vec![]));
+        f.order = Some(NoteOrder::UpdatedDesc);
+        f.after = Some(notes[0].cursor());
+        assert!(matches!(ps.query_notes(&f), Err(Error::BadFilter(_))));
+        let mut f = Filter::new(And(vec![]));
+        f.text = Some("content");
+        f.after = Some(notes[0].cursor());
+        assert!(matches!(ps.query_notes(&f), Err(Error::BadFilter(_))));
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        for i in 0..100 {
+            let content = format!("content-{}", i);
+            let tag = if i % 2 == 0 { "even" } else { "odd" };
+            assert!(ps.create_note(&content, vec![tag]).is_ok());
+        }
+        let f = SimpleTokenizer::new().tokenize("even").unwrap();
+        let mut count = 0;
+        ps.scan_notes(&f, &mut |n| {
+            assert_eq!(n.content, format!("content-{}", 98 - count * 2));
+            count += 1;
+            true
+        })
+        .unwrap();
+        assert_eq!(count, 50);
+        // Scanning stops once the callback returns false.
+        let mut contents = Vec::<String>::new();
+        ps.scan_notes(&f, &mut |n| {
+            contents.push(n.content);
+            contents.len() < 3
+        })
+        .unwrap();
+        assert_eq!(contents, vec!["content-98", "content-96", "content-94"]);
+    }
+
+    #[test]
+    fn test_note_id() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps.create_note("first #a", vec!["a"]).is_ok());
+        assert!(ps.create_note("second #a", vec!["a"]).is_ok());
+        let notes = query(&ps, "a");
+        assert_ne!(notes[0].id, notes[1].id);
+        let n = ps.get_note_by_id(&notes[1].id).unwrap();
+        assert_eq!(n.content, "first #a");
+
+        // IDs stay the same across updates.
+        assert!(ps
+            .update_note_by_hash(&n.hash, "first, edited #a", vec!["a"])
+            .is_ok());
+        let edited = ps.get_note_by_id(&n.id).unwrap();
+        assert_eq!(edited.content, "first, edited #a");
+        assert_ne!(edited.hash, n.hash);
+        assert!(matches!(
+            ps.get_note_by_id("no-such-id"),
+            Err(Error::NoteNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_note_id_backfill() {
+        let path = std::env::temp_dir().join(format!("hashtags-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        {
+            let conn = rusqlite::Connection::open(path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE notes (
+                    hash                 BLOB PRIMARY KEY,
+                    content              TEXT NOT NULL,
+                    time_created         DATETIME NOT NULL,
+                    time_updated         DATETIME
+                 );
+                 INSERT INTO notes VALUES (x'01', 'one #a', '2020-05-01T00:00:00Z', NULL);
+                 INSERT INTO notes VALUES (x'02', 'two #a', '2020-05-02T00:00:00Z', NULL);",
+            )
+            .unwrap();
+        }
+        let ps = SqlitePersistence::new(path).unwrap();
+        let ids: Vec<String> = ps
+            .query_notes(&Filter::new(And(vec![])))
+            .unwrap()
+            .into_iter()
+            .map(|n| n.id)
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert!(!ids[0].is_empty());
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(ps.get_note_by_id(&ids[1]).unwrap().content, "one #a");
+        drop(ps);
+        // Existing IDs are kept when opened again.
+        let ps = SqlitePersistence::new(path).unwrap();
+        assert_eq!(ps.get_note_by_id(&ids[0]).unwrap().content, "two #a");
+        drop(ps);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_revisions() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps.create_note("draft #a", vec!["a"]).is_ok());
+        let first = query(&ps, "a").remove(0);
+        let revs = ps.list_revisions(&first.id).unwrap();
+        assert_eq!(revs.len(), 1);
+        assert_eq!(revs[0].number, 1);
+        assert_eq!(revs[0].content, "draft #a");
+        assert_eq!(revs[0].hash, first.hash);
+        assert_eq!(revs[0].time_created, first.time_created);
+
+        assert!(ps
+            .update_note_by_hash(&first.hash, "second #a", vec!["a"])
+            .is_ok());
+        let second = query(&ps, "a").remove(0);
+        // Updates without changes don't make a revision.
+        assert!(ps
+            .update_note_by_hash(&second.hash, "second #a", vec!["a"])
+            .is_ok());
+        let second = query(&ps, "a").remove(0);
+        assert_eq!(ps.rename_tag("a", "b", false, &ranges).unwrap(), 1);
+        let n = query(&ps, "b").remove(0);
+        let revs = ps.list_revisions(&n.id).unwrap();
+        let contents: Vec<&str> = revs.iter().map(|r| r.content.as_str()).collect();
+        assert_eq!(contents, vec!["draft #a", "second #a", "second #b"]);
+        let numbers: Vec<u32> = revs.iter().map(|r| r.number).collect();
+        assert_eq!(numbers, vec![1, 2, 3]);
+        assert_eq!(revs[0].time_created, first.time_created);
+        assert_eq!(Some(revs[1].time_created), second.time_updated);
+        assert_eq!(Some(revs[2].time_created), n.time_updated);
+        assert_eq!(revs[2].hash, n.hash);
+        assert!(matches!(
+            ps.list_revisions("no-such-id"),
+            Err(Error::NoteNotFound(_))
+        ));
+
+        // Revisions stay in trash, and go away once the note is purged.
+        assert!(ps.delete_note_by_hash(&n.hash).is_ok());
+        assert_eq!(ps.list_revisions(&n.id).unwrap().len(), 3);
+        assert_eq!(
+            ps.purge_trash(&(Utc::now() + chrono::Duration::seconds(1)))
+                .unwrap(),
+            1
+        );
+        let left: u32 = ps
+            .conn
+            .query_row("SELECT COUNT(*) FROM revisions", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn test_rename_tag() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps.create_note("content-1 #k8s", vec!["k8s"]).is_ok());
+        assert!(ps
+            .create_note("content-2 #k8s #ops", vec!["k8s", "ops"])
+            .is_ok());
+        assert!(ps
+            .create_note("content-3 #kubernetes", vec!["kubernetes"])
+            .is_ok());
+        assert!(ps
+            .create_note(
+                "content-4 #k8s #due:2026-11-01",
+                vec!["k8s", "due:2026-11-01"]
+            )
+            .is_ok());
+        // Renaming to an existing tag is rejected without merging.
+        assert!(matches!(
+            ps.rename_tag("k8s", "kubernetes", false, &ranges),
+            Err(Error::TagExists(_))
+        ));
+        assert!(matches!(
+            ps.rename_tag("k9s", "kubernetes", true, &ranges),
+            Err(Error::TagNotFound(_))
+        ));
+        assert_eq!(ps.rename_tag("ops", "devops", false, &ranges).unwrap(), 1);
+        let notes = query(&ps, "devops,kubernetes|k8s");
+        assert!(notes.len() == 1 && notes[0].content == "content-2 #k8s #devops");
+        assert!(query(&ps, "ops").is_empty());
+        assert_eq!(
+            ps.rename_tag("k8s", "kubernetes", true, &ranges).unwrap(),
+            3
+        );
+        assert!(query(&ps, "k8s").is_empty());
+        let notes = query(&ps, "kubernetes");
+        assert_eq!(notes.len(), 4);
+        // Attributes follow the rewritten note.
+        let notes = query(&ps, "kubernetes,due<2026-12-01");
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].content, "content-4 #kubernetes #due:2026-11-01");
+        assert_eq!(notes[0].attributes[0].value, "2026-11-01");
+        let notes = query(&ps, "kubernetes,devops");
+        assert!(notes.len() == 1 && notes[0].content == "content-2 #kubernetes #devops");
+        let tags: Vec<String> = ps
+            .list_tags(&TagOrder::Name)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(tags, vec!["devops", "kubernetes"]);
+        // The rewritten note should be addressed by its new hash.
+        let hash = base64::encode(&notes[0].hash);
+        let note = ps.get_note_by_hash_prefix(&hash).unwrap();
+        assert_eq!(note.hash, super::content_hash(&note.content));
+
+        // Tags are rewritten only where the extractor finds them.
+        let md = |content: &str| MarkdownExtractor::new().tag_ranges(content);
+        assert!(ps
+            .create_note("#devops `#devops`\n```\n#devops\n```", vec!["devops"])
+            .is_ok());
+        assert_eq!(ps.rename_tag("devops", "ops", false, &md).unwrap(), 2);
+        let notes = query(&ps, "ops");
+        assert!(notes
+            .iter()
+            .any(|n| n.content == "#ops `#devops`\n```\n#devops\n```"));
+    }
+
+    #[test]
+    fn test_list_tags() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps.create_note("content-1", vec!["b", "c"]).is_ok());
+        assert!(ps.create_note("content-2", vec!["a", "c"]).is_ok());
+        assert!(ps.create_note("content-3", vec!["c", "d"]).is_ok());
+        let names = |order| {
+            ps.list_tags(&order)
+                .unwrap()
+                .into_iter()
+                .map(|t| (t.name, t.count))
+                .collect::<Vec<(String, u32)>>()
+        };
+        assert_eq!(
+            names(TagOrder::Name),
+            vec![
+                ("a".to_string(), 1),
+                ("b".to_string(), 1),
+                ("c".to_string(), 3),
+                ("d".to_string(), 1)
+            ]
+        );
+        assert_eq!(names(TagOrder::Count)[0], ("c".to_string(), 3));
+        assert_eq!(names(TagOrder::Recency)[0], ("c".to_string(), 3));
+        assert_eq!(names(TagOrder::Recency)[1], ("d".to_string(), 1));
+        // Tags no longer used by any note are still listed.
+        let notes = query(&ps, "b");
+        ps.delete_note_by_hash(&notes[0].hash).unwrap();
+        let tags = ps.list_tags(&TagOrder::Name).unwrap();
+        assert!(tags[1].name == "b" && tags[1].count == 0 && tags[1].time_last_used.is_none());
+        assert_eq!(
+            tags[2].time_last_used,
+            Some(query(&ps, "d")[0].time_created)
+        );
+    }
+
+    #[test]
+    fn test_normalize_tags() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps.create_note("a #Rust", vec!["Rust"]).is_ok());
+        assert!(ps.create_note("b #rust", vec!["rust"]).is_ok());
+        assert!(ps
+            .create_note("c #cafe\u{301}", vec!["cafe\u{301}"])
+            .is_ok());
+        assert!(ps.create_note("d #caf\u{e9}", vec!["caf\u{e9}"]).is_ok());
+        let tags = |ps: &SqlitePersistence| {
+            ps.list_tags(&TagOrder::Name)
+                .unwrap()
+                .into_iter()
+                .map(|t| (t.name, t.display, t.count))
+                .collect::<Vec<(String, String, u32)>>()
+        };
+        // Tags are always in NFC, but case is kept by default.
+        assert_eq!(
+            tags(&ps),
+            vec![
+                ("Rust".to_string(), "Rust".to_string(), 1),
+                ("caf\u{e9}".to_string(), "cafe\u{301}".to_string(), 2),
+                ("rust".to_string(), "rust".to_string(), 1)
+            ]
+        );
+        assert_eq!(query(&ps, "caf\u{e9}").len(), 2);
+
+        let extract = |content: &str| -> Vec<String> {
+            match extract_tags(content) {
+                Ok(tags) => tags.into_iter().map(String::from).collect(),
+                Err(_) => vec![],
+            }
+        };
+        ps.normalize_tags(true, &extract).unwrap();
+        assert!(ps.normalizer().fold_case);
+        let folded = tags(&ps);
+        assert_eq!(folded.len(), 2);
+        assert_eq!(folded[1].0, "rust");
+        assert_eq!(folded[1].2, 2);
+        assert_eq!(query(&ps, "rust").len(), 2);
+
+        // New notes and renames follow the setting.
+        assert!(ps.create_note("e #RUST", vec!["RUST"]).is_ok());
+        assert_eq!(query(&ps, "rust").len(), 3);
+        assert_eq!(ps.rename_tag("RUST", "Lang", false, &ranges).unwrap(), 3);
+        let mut contents: Vec<String> = query(&ps, "lang").into_iter().map(|n| n.content).collect();
+        contents.sort();
+        assert_eq!(contents, vec!["a #Lang", "b #Lang", "e #Lang"]);
+        assert_eq!(tags(&ps)[1], ("lang".to_string(), "Lang".to_string(), 3));
+        assert!(matches!(
+            ps.rename_tag("lang", "LANG", true, &ranges),
+            Err(Error::TagExists(_))
+        ));
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let mut ps = SqlitePersistence::new(":memory:").unwrap();
+        assert!(ps
+            .create_note("bug #project/hashtags/bugs", vec!["project/hashtags/bugs"])
+            .is_ok());
+        assert!(ps
+            .create_note("other #project/other", vec!["project/other"])
+            .is_ok());
+        assert!(ps
+            .create_note("todo #project/hashtags", vec!["project/hashtags"])
+            .is_ok());
+        assert!(ps.create_note("plural #projects", vec!["projects"]).is_ok());
+        let contents = |ps: &SqlitePersistence, q| -> Vec<String> {
+            query(ps, q).into_iter().map(|n| n.content).collect()
+        };
+        assert_eq!(
+            contents(&ps, "project/hashtags/bugs"),
+            vec!["bug #project/hashtags/bugs"]
+        );
+        assert_eq!(
+            contents(&ps, "project/hashtags"),
+            vec!["todo #project/hashtags", "bug #project/hashtags/bugs"]
+        );
+        assert_eq!(contents(&ps, "project").len(), 3);
+        assert_eq!(
+            contents(&ps, "project,-project/hashtags"),
+            vec!["other #project/other"]
+        );
+        let f = ExprTokenizer::new()
+            .tokenize("project & !project/hashtags/bugs")
+            .unwrap();
+        assert_eq!(ps.query_notes(&f).unwrap().len(), 2);
+
+        // Ancestors are listed with their parents, even if no note uses them.
+        let tags: Vec<(String, Option<String>, u32)> = ps
+            .list_tags(&TagOrder::Name)
+            .unwrap()
+            .into_iter()
+            .map(|t| (t.name, t.parent, t.count))
+            .collect();
+        let tag = |name: &str, parent: Option<&str>, count| {
+            (name.to_string(), parent.map(String::from), count)
+        };
+        assert_eq!(
+            tags,
+            vec![
+                tag("project", None, 0),
+                tag("project/hashtags", Some("project"), 1),
+                tag("project/hashtags/bugs", Some("project/hashtags"), 1),
+                tag("project/other", Some("project"), 1),
+                tag("projects", None, 1),
+            ]
+        );
+
+        // Renamed tags are kept as ancestors of their descendants.
+        assert_eq!(
+            ps.rename_tag("project/hashtags", "project/ht", false, &ranges)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            contents(&ps, "project/hashtags"),
+            vec!["bug #project/hashtags/bugs"]
+        );
+        assert_eq!(contents(&ps, "project/ht"), vec!["todo #project/ht"]);
+        let names: Vec<String> = ps
+            .list_tags(&TagOrder::Name)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert!(names.contains(&"project/hashtags".to_string()));
+    }
+
     #[test]
     fn test_utf8() {
         let mut ps = SqlitePersistence::new(":memory:").unwrap();
@@ -301,15 +1840,13 @@ mod test {
                 vec!["台達電", "2308", "2017", "現貨"]
             )
             .is_err());
-        let notes = ps
-            .query_notes(&vec!["2018", "現貨"], &vec!["台積電", "台達電"])
-            .unwrap();
+        let notes = query(&ps, "2018,現貨,台積電|台達電");
         assert!(
             notes.len() == 2
                 && notes[0].content.starts_with("content-2")
                 && notes[1].content.starts_with("content-1")
         );
-        let notes = ps.query_notes(&vec!["2017"], &vec!["現貨", "ETF"]).unwrap();
+        let notes = query(&ps, "2017,現貨|ETF");
         assert!(
             notes.len() == 2
                 && notes[0].content.starts_with("content-4")
@@ -326,7 +1863,7 @@ mod test {
                 vec!["台積電", "2330", "2018", "現貨"]
             )
             .is_err());
-        let notes = ps.query_notes(&vec!["台積電"], &vec![]).unwrap();
+        let notes = query(&ps, "台積電");
         assert!(notes.len() == 1 && notes[0].content.starts_with("content-1"));
         ps.update_note_by_hash(
             &notes[0].hash,
@@ -334,7 +1871,7 @@ mod test {
             vec!["台積電", "2330", "2018", "現貨"],
         )
         .unwrap();
-        let notes = ps.query_notes(&vec!["台積電"], &vec![]).unwrap();
+        let notes = query(&ps, "台積電");
         assert!(notes.len() == 1 && notes[0].content.starts_with("content-2"));
     }
 }
@@ -1,20 +1,86 @@
 use std::result::Result;
+use std::string::String;
 use std::vec::Vec;
 use super::error::Error;
 use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
 
-pub fn extract_tags(note: &str) -> Result<Vec<&str>, Error> {
-    let re = Regex::new(r"((^|\s)#[^\s\t\.\?#,]+)").unwrap();
-    let mut tags = Vec::<&str>::new();
+// Tags are hierarchical with '/' separating levels, like '#project/bugs'.
+const TAG_PATTERN: &str = r"((^|\s)#[^\s\t\.\?#,/]+(/[^\s\t\.\?#,/]+)*)";
+
+// Extract tags from contents of notes, duplicated tags are removed and an
+// error is returned when there is no tag at all.
+pub trait TagExtractor {
+    // Byte ranges of tags in note, without the leading '#'. Tags are
+    // rewritten only there, ex. by 'replace_tag_by'.
+    fn tag_ranges(&self, note: &str) -> Vec<(usize, usize)>;
+
+    fn extract_tags<'a>(&self, note: &'a str) -> Result<Vec<&'a str>, Error> {
+        collect_tags(note, self.tag_ranges(note))
+    }
+}
+
+// Tags are words starting with '#', the default.
+#[derive(Default)]
+pub struct RegexExtractor {
+}
+
+impl RegexExtractor {
+    pub fn new() -> RegexExtractor {
+        RegexExtractor{}
+    }
+}
+
+impl TagExtractor for RegexExtractor {
+    fn tag_ranges(&self, note: &str) -> Vec<(usize, usize)> {
+        find_tags(note)
+    }
+}
+
+// Same as 'RegexExtractor', except that tags in Markdown code spans and
+// fenced code blocks are ignored. Fragments of URLs are never taken as tags
+// by either, since tags have to follow a whitespace.
+#[derive(Default)]
+pub struct MarkdownExtractor {
+}
+
+impl MarkdownExtractor {
+    pub fn new() -> MarkdownExtractor {
+        MarkdownExtractor{}
+    }
+}
+
+impl TagExtractor for MarkdownExtractor {
+    fn tag_ranges(&self, note: &str) -> Vec<(usize, usize)> {
+        // Ignored parts are blanked out, so positions in the masked note are
+        // the same as those in the original one.
+        let mut masked = note.as_bytes().to_vec();
+        for (start, end) in markdown_ignored(note) {
+            for b in &mut masked[start..end] {
+                *b = b' ';
+            }
+        }
+        let masked = String::from_utf8(masked).unwrap();
+        find_tags(&masked)
+    }
+}
+
+// Byte ranges of tags found in note, without the leading '#'.
+fn find_tags(note: &str) -> Vec<(usize, usize)> {
+    let re = Regex::new(TAG_PATTERN).unwrap();
+    let mut ranges = Vec::new();
     for m in re.find_iter(note) {
-        let start = match note[m.start()..].find("#") {
-            Some(i) => m.start()+i+1,
-            None => return Err(Error::GenericError(format!("unable to find # {}", note)))
-        };
-        tags.push(&note[start..m.end()]);
+        if let Some(i) = note[m.start()..].find('#') {
+            ranges.push((m.start()+i+1, m.end()));
+        }
     }
-    if tags.len() == 0 {
-        return Err(Error::GenericError(format!("no tags extracted: {}", note)))
+    ranges
+}
+
+fn collect_tags(note: &str, ranges: Vec<(usize, usize)>) -> Result<Vec<&str>, Error> {
+    let mut tags: Vec<&str> = ranges.into_iter().map(|(start, end)| &note[start..end]).collect();
+    if tags.is_empty() {
+        return Err(Error::NoTags(note.to_string()))
     }
     // Remove duplications.
     tags.sort();
@@ -22,23 +88,244 @@ pub fn extract_tags(note: &str) -> Result<Vec<&str>, Error> {
     Ok(tags)
 }
 
+// Byte ranges of fenced code blocks and code spans in a Markdown note.
+fn markdown_ignored(note: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut fence: Option<&str> = None;
+    let mut pos = 0;
+    for line in note.split_inclusive('\n') {
+        let start = pos;
+        pos += line.len();
+        let trimmed = line.trim_start();
+        let marker = if trimmed.starts_with("```") {
+            Some("```")
+        } else if trimmed.starts_with("~~~") {
+            Some("~~~")
+        } else {
+            None
+        };
+        match (fence, marker) {
+            (Some(f), Some(m)) if f == m => {
+                fence = None;
+                ranges.push((start, pos));
+            }
+            (Some(_), _) => ranges.push((start, pos)),
+            (None, Some(m)) => {
+                fence = Some(m);
+                ranges.push((start, pos));
+            }
+            (None, None) => {
+                ranges.extend(code_spans(line).into_iter().map(|(s, e)| (start+s, start+e)));
+            }
+        }
+    }
+    ranges
+}
+
+// A code span starts with a run of backticks and ends with a run of the same
+// length, unclosed runs are left as they are.
+fn code_spans(line: &str) -> Vec<(usize, usize)> {
+    let bytes = line.as_bytes();
+    let run = |from: usize| bytes[from..].iter().take_while(|&&b| b == b'`').count();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'`' {
+            i += 1;
+            continue;
+        }
+        let n = run(i);
+        let mut j = i + n;
+        let mut closed = false;
+        while j < bytes.len() {
+            if bytes[j] != b'`' {
+                j += 1;
+                continue;
+            }
+            let m = run(j);
+            j += m;
+            if m == n {
+                closed = true;
+                break;
+            }
+        }
+        if closed {
+            spans.push((i, j));
+            i = j;
+        } else {
+            i += n;
+        }
+    }
+    spans
+}
+
+// Tags are compared in Unicode NFC, and case-insensitively with 'fold_case',
+// notes keep tags as they are written.
+#[derive(Clone, Copy, Default)]
+pub struct Normalizer {
+    pub fold_case: bool,
+}
+
+impl Normalizer {
+    pub fn new(fold_case: bool) -> Normalizer {
+        Normalizer{ fold_case }
+    }
+
+    pub fn normalize(&self, tag: &str) -> String {
+        let tag: String = tag.nfc().collect();
+        if self.fold_case {
+            return tag.to_lowercase();
+        }
+        tag
+    }
+}
+
+pub fn extract_tags(note: &str) -> Result<Vec<&str>, Error> {
+    collect_tags(note, find_tags(note))
+}
+
+// Parent of a hierarchical tag, ex. 'project' for 'project/bugs'.
+pub fn parent_tag(tag: &str) -> Option<&str> {
+    tag.rfind('/').map(|i| &tag[..i])
+}
+
+// Key and value of a key-value tag, ex. 'priority' and 'high' for
+// 'priority:high'. The value might contain ':' as well, ex. 'at:10:30'.
+pub fn split_attribute(tag: &str) -> Option<(&str, &str)> {
+    match tag.find(':') {
+        Some(i) if i > 0 && i+1 < tag.len() => Some((&tag[..i], &tag[i+1..])),
+        _ => None,
+    }
+}
+
+pub fn is_valid_tag(tag: &str) -> bool {
+    match extract_tags(&format!("#{}", tag)) {
+        Ok(tags) => tags == vec![tag],
+        Err(_) => false,
+    }
+}
+
+// Replace all occurrences of tag 'from' in note by 'to', the rest of the
+// note is untouched.
+pub fn replace_tag(note: &str, from: &str, to: &str) -> String {
+    replace_tag_by(note, find_tags(note), |t| t == from, to)
+}
+
+// Same as 'replace_tag', but tags are those at 'ranges' given by
+// 'TagExtractor::tag_ranges', and tags to replace are those 'matches'
+// accepts.
+pub fn replace_tag_by<F>(note: &str, ranges: Vec<(usize, usize)>, matches: F, to: &str) -> String
+where
+    F: Fn(&str) -> bool,
+{
+    let mut replaced = String::with_capacity(note.len());
+    let mut last = 0;
+    for (start, end) in ranges {
+        if matches(&note[start..end]) {
+            replaced.push_str(&note[last..start]);
+            replaced.push_str(to);
+            last = end;
+        }
+    }
+    replaced.push_str(&note[last..]);
+    replaced
+}
+
 #[cfg(test)]
 mod test {
-    use super::extract_tags;
+    use super::{extract_tags, is_valid_tag, replace_tag};
+    use super::{replace_tag_by, MarkdownExtractor, Normalizer, RegexExtractor, TagExtractor};
+    use super::split_attribute;
+    use super::parent_tag;
+    use super::super::error::Error;
 
     #[test]
     fn test_basic() {
-        assert!(extract_tags("kdfkjsdkfjsf").is_err());
+        assert!(matches!(extract_tags("kdfkjsdkfjsf"), Err(Error::NoTags(_))));
         assert_eq!(extract_tags("ss #ss #tt # sdkjfk #yy").unwrap(), vec!["ss", "tt", "yy"]);
         assert_eq!(extract_tags("#ss").unwrap(), vec!["ss"]);
         assert_eq!(extract_tags("#ss #tt #ss").unwrap(), vec!["ss", "tt"]);
     }
 
+    #[test]
+    fn test_replace() {
+        assert_eq!(replace_tag("#k8s", "k8s", "kubernetes"), "#kubernetes");
+        assert_eq!(
+            replace_tag("a #k8s, #k8s-dev #k8s. a#k8s #k8s#k8s", "k8s", "kubernetes"),
+            "a #kubernetes, #k8s-dev #kubernetes. a#k8s #kubernetes#k8s");
+        assert_eq!(replace_tag("no tags", "k8s", "kubernetes"), "no tags");
+        assert_eq!(replace_tag("#測試 #幹嘛 測試", "測試", "哎呦"), "#哎呦 #幹嘛 測試");
+        assert!(is_valid_tag("k8s"));
+        assert!(is_valid_tag("台積電"));
+        assert!(!is_valid_tag(""));
+        assert!(!is_valid_tag("k 8s"));
+        assert!(!is_valid_tag("k8s."));
+    }
+
     #[test]
     fn test_utf8() {
-        assert!(extract_tags("我家門前有小河").is_err());
+        assert!(matches!(extract_tags("我家門前有小河"), Err(Error::NoTags(_))));
         assert_eq!(extract_tags("ss #測試 #哎呦 # sdkjfk #幹嘛").unwrap(), vec!["哎呦", "幹嘛", "測試"]);
         assert_eq!(extract_tags("#再測").unwrap(), vec!["再測"]);
         assert_eq!(extract_tags("ss #幹嘛 #測試  # sdkjfk #幹嘛").unwrap(), vec!["幹嘛", "測試"]);
     }
+
+    #[test]
+    fn test_markdown() {
+        let note = "#a `#b` ``#c ` #d`` #e\n\
+                    ```\n\
+                    #f\n\
+                    ~~~\n\
+                    #g\n\
+                    ```\n\
+                    #h https://example.com/#i ` #k\n\
+                    ~~~rust\n\
+                    #l";
+        let t = MarkdownExtractor::new();
+        assert_eq!(t.extract_tags(note).unwrap(), vec!["a", "e", "h", "k"]);
+        assert_eq!(RegexExtractor::new().extract_tags(note).unwrap(),
+                   vec!["a", "d``", "e", "f", "g", "h", "k", "l"]);
+        assert!(matches!(t.extract_tags("`#a`\n```\n#b"), Err(Error::NoTags(_))));
+        assert_eq!(t.extract_tags("#測試 `#幹嘛` #哎呦").unwrap(), vec!["哎呦", "測試"]);
+        // Tags in code are left as they are when replaced.
+        let note = "#a `#a`\n```\n#a\n```\n#a";
+        assert_eq!(replace_tag_by(note, t.tag_ranges(note), |tag| tag == "a", "b"), "#b `#a`\n```\n#a\n```\n#b");
+    }
+
+    #[test]
+    fn test_normalize() {
+        let n = Normalizer::new(false);
+        assert_eq!(n.normalize("cafe\u{301}"), "caf\u{e9}");
+        assert_eq!(n.normalize("Caf\u{e9}"), "Caf\u{e9}");
+        let n = Normalizer::new(true);
+        assert_eq!(n.normalize("Cafe\u{301}"), "caf\u{e9}");
+        assert_eq!(n.normalize("RUST"), "rust");
+        assert_eq!(n.normalize("台積電"), "台積電");
+        let note = "#Rust #rust #rusty";
+        let ranges = RegexExtractor::new().tag_ranges(note);
+        assert_eq!(replace_tag_by(note, ranges, |t| n.normalize(t) == "rust", "rs"), "#rs #rs #rusty");
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        assert_eq!(extract_tags("#project/hashtags/bugs #a/b, #c/ #d//e #/f").unwrap(),
+                   vec!["a/b", "c", "d", "project/hashtags/bugs"]);
+        assert_eq!(parent_tag("project/hashtags/bugs"), Some("project/hashtags"));
+        assert_eq!(parent_tag("project"), None);
+        assert!(is_valid_tag("project/hashtags"));
+        assert!(!is_valid_tag("project/"));
+        assert!(!is_valid_tag("project//bugs"));
+        assert_eq!(replace_tag("#a/b #a", "a", "c"), "#a/b #c");
+    }
+
+    #[test]
+    fn test_attribute() {
+        assert_eq!(extract_tags("due #due:2026-11-01, #at:10:30").unwrap(),
+                   vec!["at:10:30", "due:2026-11-01"]);
+        assert_eq!(split_attribute("due:2026-11-01"), Some(("due", "2026-11-01")));
+        assert_eq!(split_attribute("at:10:30"), Some(("at", "10:30")));
+        assert_eq!(split_attribute("due"), None);
+        assert_eq!(split_attribute(":high"), None);
+        assert_eq!(split_attribute("priority:"), None);
+    }
 }
@@ -0,0 +1,247 @@
+use super::core::HashTags;
+use super::error::Error;
+use super::model::Note;
+use super::tag::is_valid_tag;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::string::String;
+use std::vec::Vec;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Todo,
+    Doing,
+    Done,
+}
+
+const STATES: [State; 3] = [State::Todo, State::Doing, State::Done];
+
+#[derive(Serialize)]
+pub struct Task {
+    pub state: State,
+    pub note: Note,
+}
+
+// Tasks of a project, ex. 'hashtags' for '#project/hashtags'. Tasks without
+// projects are grouped with no name.
+#[derive(Serialize)]
+pub struct Project {
+    pub name: Option<String>,
+    pub tasks: Vec<Task>,
+}
+
+// Notes tagged with a state tag are tasks, and belong to the projects they
+// are tagged with under 'project', ex. '#todo #project/hashtags'. The text
+// of notes stays the source of truth, states change by rewriting the tags.
+pub struct Tasks {
+    pub todo: String,
+    pub doing: String,
+    pub done: String,
+    pub project: String,
+}
+
+impl Default for Tasks {
+    fn default() -> Tasks {
+        Tasks {
+            todo: "todo".to_string(),
+            doing: "doing".to_string(),
+            done: "done".to_string(),
+            project: "project".to_string(),
+        }
+    }
+}
+
+// State tags are used in filters as they are, they can't be patterns or
+// key-value tags.
+fn check_tag(tag: &str) -> Result<String, Error> {
+    if !is_valid_tag(tag) || tag.contains(&['*', ':', '|'][..]) {
+        return Err(Error::InvalidTag(tag.to_string()));
+    }
+    Ok(tag.to_string())
+}
+
+impl Tasks {
+    // State tags given as 'todo,doing,done'.
+    pub fn with_states(states: &str) -> Result<Tasks, Error> {
+        let tags: Vec<&str> = states.split(',').map(str::trim).collect();
+        match tags[..] {
+            [todo, doing, done] => Ok(Tasks {
+                todo: check_tag(todo)?,
+                doing: check_tag(doing)?,
+                done: check_tag(done)?,
+                ..Tasks::default()
+            }),
+            _ => Err(Error::InvalidTag(states.to_string())),
+        }
+    }
+
+    pub fn tag(&self, state: State) -> &str {
+        match state {
+            State::Todo => &self.todo,
+            State::Doing => &self.doing,
+            State::Done => &self.done,
+        }
+    }
+
+    // State of a note by its tags, the furthest one wins if there are many.
+    fn state_of(&self, hs: &HashTags, content: &str) -> Option<State> {
+        let n = hs.normalizer();
+        let tags = hs.tags_of(content);
+        STATES
+            .iter()
+            .rev()
+            .find(|s| tags.contains(&n.normalize(self.tag(**s))))
+            .copied()
+    }
+
+    // Tasks not done yet, grouped by projects ordered by name, tasks without
+    // projects come last. A task with several projects is in each of them.
+    pub fn open(&self, hs: &HashTags) -> Result<Vec<Project>, Error> {
+        let filter = format!("-{},{}|{}", self.done, self.todo, self.doing);
+        let prefix = format!("{}/", hs.normalizer().normalize(&self.project));
+        let mut projects = BTreeMap::<String, Vec<Task>>::new();
+        let mut others = Vec::<Task>::new();
+        for note in hs.query("simple", &filter)? {
+            // Descendants of state tags are matched by the filter, but they
+            // aren't states.
+            let state = match self.state_of(hs, &note.content) {
+                Some(state) => state,
+                None => continue,
+            };
+            let names: Vec<String> = hs
+                .tags_of(&note.content)
+                .iter()
+                .filter_map(|t| t.strip_prefix(&prefix).map(String::from))
+                .collect();
+            if names.is_empty() {
+                others.push(Task { state, note });
+                continue;
+            }
+            for name in names {
+                let note = note.clone();
+                projects.entry(name).or_default().push(Task { state, note });
+            }
+        }
+        let mut groups: Vec<Project> = projects
+            .into_iter()
+            .map(|(name, tasks)| Project {
+                name: Some(name),
+                tasks,
+            })
+            .collect();
+        if !others.is_empty() {
+            groups.push(Project {
+                name: None,
+                tasks: others,
+            });
+        }
+        Ok(groups)
+    }
+
+    // Rewrite the state tags of a task as the one of 'state', returns the
+    // updated note.
+    pub fn set_state(&self, hs: &mut HashTags, key: &str, state: State) -> Result<Note, Error> {
+        let note = hs.show(key)?;
+        match self.state_of(hs, &note.content) {
+            None => return Err(Error::NotATask(key.to_string())),
+            Some(s) if s == state => return Ok(note),
+            _ => (),
+        };
+        let n = hs.normalizer();
+        let tags: Vec<String> = STATES.iter().map(|s| n.normalize(self.tag(*s))).collect();
+        let content = hs.replace_tags(
+            &note.content,
+            |t| tags.contains(&n.normalize(t)),
+            self.tag(state),
+        );
+        hs.update(&content, &note.id)?;
+        hs.show(&note.id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::core::HashTags;
+    use super::super::error::Error;
+    use super::super::tag::MarkdownExtractor;
+    use super::{State, Tasks};
+
+    #[test]
+    fn test_basic() {
+        let mut hs = HashTags::new(":memory:").unwrap();
+        hs.create("fix the bug #todo #project/hashtags").unwrap();
+        hs.create("write docs #doing #project/hashtags #project/site")
+            .unwrap();
+        hs.create("buy milk #todo").unwrap();
+        hs.create("ship it #done #project/hashtags").unwrap();
+        hs.create("idea #project/site").unwrap();
+        let tasks = Tasks::default();
+        let groups = tasks.open(&hs).unwrap();
+        let summary: Vec<(Option<&str>, Vec<&str>)> = groups
+            .iter()
+            .map(|p| {
+                let contents = p.tasks.iter().map(|t| &t.note.content[..4]).collect();
+                (p.name.as_deref(), contents)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Some("hashtags"), vec!["writ", "fix "]),
+                (Some("site"), vec!["writ"]),
+                (None, vec!["buy "]),
+            ]
+        );
+        assert_eq!(groups[0].tasks[0].state, State::Doing);
+        assert_eq!(groups[0].tasks[1].state, State::Todo);
+
+        // States are changed by rewriting the content.
+        let key = groups[0].tasks[1].note.id.clone();
+        let note = tasks.set_state(&mut hs, &key, State::Done).unwrap();
+        assert_eq!(note.content, "fix the bug #done #project/hashtags");
+        assert_eq!(hs.history(&key).unwrap().len(), 2);
+        let groups = tasks.open(&hs).unwrap();
+        assert_eq!(groups[0].tasks.len(), 1);
+        // Setting the same state leaves the note as it is.
+        tasks.set_state(&mut hs, &key, State::Done).unwrap();
+        assert_eq!(hs.history(&key).unwrap().len(), 2);
+
+        let key = hs.query("simple", "project/site").unwrap()[0].id.clone();
+        assert!(matches!(
+            tasks.set_state(&mut hs, &key, State::Done),
+            Err(Error::NotATask(_))
+        ));
+    }
+
+    #[test]
+    fn test_states() {
+        let mut hs = HashTags::new(":memory:").unwrap();
+        hs.create("call Bob #TODO #Project/Home").unwrap();
+        hs.normalize_tags(true).unwrap();
+        let tasks = Tasks::with_states("todo, wip, closed").unwrap();
+        let groups = tasks.open(&hs).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].name.as_deref(), Some("home"));
+        let key = groups[0].tasks[0].note.id.clone();
+        let note = tasks.set_state(&mut hs, &key, State::Doing).unwrap();
+        assert_eq!(note.content, "call Bob #wip #Project/Home");
+
+        // State tags in code are left as they are.
+        let mut hs =
+            HashTags::with_extractor(":memory:", Box::new(MarkdownExtractor::new())).unwrap();
+        hs.create("run `#todo` #todo").unwrap();
+        let key = hs.query("simple", "todo").unwrap()[0].id.clone();
+        let note = tasks.set_state(&mut hs, &key, State::Done).unwrap();
+        assert_eq!(note.content, "run `#todo` #closed");
+
+        assert!(matches!(
+            Tasks::with_states("todo,done"),
+            Err(Error::InvalidTag(_))
+        ));
+        assert!(matches!(
+            Tasks::with_states("todo,doing,done:yes"),
+            Err(Error::InvalidTag(_))
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+use super::error::Error;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use std::result::Result;
+
+// Parse a lower bound of time, accepted forms are:
+//  - RFC 3339 timestamps, ex. 2020-05-01T08:00:00+08:00
+//  - timestamps without timezone in UTC, ex. 2020-05-01T08:00:00
+//  - dates in UTC, ex. 2020-05-01, which stand for the start of that day
+//  - "now", "today", "yesterday"
+//  - durations before now in hours, days or weeks, ex. 12h, 7d, 2w
+pub fn parse_since(s: &str) -> Result<DateTime<Utc>, Error> {
+    parse_time(s, Utc::now(), false)
+}
+
+// Parse an upper bound of time, forms are the same as 'parse_since' except
+// that dates, "today" and "yesterday" stand for the end of that day.
+pub fn parse_until(s: &str) -> Result<DateTime<Utc>, Error> {
+    parse_time(s, Utc::now(), true)
+}
+
+fn parse_time(s: &str, now: DateTime<Utc>, day_end: bool) -> Result<DateTime<Utc>, Error> {
+    let s = s.trim();
+    let day = |date: NaiveDate| {
+        let date = if day_end {
+            date.succ_opt().unwrap_or(date)
+        } else {
+            date
+        };
+        Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+    };
+    match s {
+        "now" => return Ok(now),
+        "today" => return Ok(day(now.naive_utc().date())),
+        "yesterday" => return Ok(day(now.naive_utc().date().pred_opt().unwrap())),
+        _ => (),
+    };
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&Utc));
+    }
+    if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(Utc.from_utc_datetime(&t));
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(day(d));
+    }
+    if let Some(unit) = s.chars().last() {
+        if let Ok(n) = s[..s.len() - unit.len_utf8()].parse::<i64>() {
+            let d = match unit {
+                'h' => Some(Duration::try_hours(n)),
+                'd' => Some(Duration::try_days(n)),
+                'w' => Some(Duration::try_weeks(n)),
+                _ => None,
+            };
+            if let Some(d) = d {
+                // Durations are before now, and too long ones are out of the
+                // range of times.
+                return d
+                    .filter(|_| n >= 0)
+                    .and_then(|d| now.checked_sub_signed(d))
+                    .ok_or_else(|| Error::BadFilter(format!("time out of range: '{}'", s)));
+            }
+        }
+    }
+    Err(Error::BadFilter(format!("unable to parse time: '{}'", s)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::error::Error;
+    use super::parse_time;
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn test_basic() {
+        let now = Utc.with_ymd_and_hms(2020, 5, 10, 12, 30, 0).unwrap();
+        let at = |y, m, d, h| Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap();
+        assert_eq!(parse_time("now", now, false).unwrap(), now);
+        assert_eq!(parse_time("today", now, false).unwrap(), at(2020, 5, 10, 0));
+        assert_eq!(parse_time("today", now, true).unwrap(), at(2020, 5, 11, 0));
+        assert_eq!(
+            parse_time("yesterday", now, false).unwrap(),
+            at(2020, 5, 9, 0)
+        );
+        assert_eq!(
+            parse_time("yesterday", now, true).unwrap(),
+            at(2020, 5, 10, 0)
+        );
+        assert_eq!(
+            parse_time("2020-04-30", now, false).unwrap(),
+            at(2020, 4, 30, 0)
+        );
+        assert_eq!(
+            parse_time("2020-04-30", now, true).unwrap(),
+            at(2020, 5, 1, 0)
+        );
+        assert_eq!(
+            parse_time("2020-04-30T08:00:00", now, true).unwrap(),
+            at(2020, 4, 30, 8)
+        );
+        assert_eq!(
+            parse_time("2020-04-30T08:00:00+08:00", now, false).unwrap(),
+            at(2020, 4, 30, 0)
+        );
+        assert_eq!(
+            parse_time("12h", now, false).unwrap(),
+            now - Duration::hours(12)
+        );
+        assert_eq!(
+            parse_time(" 7d ", now, true).unwrap(),
+            now - Duration::days(7)
+        );
+        assert_eq!(
+            parse_time("2w", now, false).unwrap(),
+            now - Duration::weeks(2)
+        );
+        assert!(parse_time("", now, false).is_err());
+        assert!(parse_time("7", now, false).is_err());
+        assert!(parse_time("7y", now, false).is_err());
+        assert!(parse_time("d", now, false).is_err());
+        assert!(parse_time("2020-13-01", now, false).is_err());
+        assert!(parse_time("明天", now, false).is_err());
+        for s in &[
+            "-1d",
+            "9999999999d",
+            "99999999999999d",
+            "9223372036854775807h",
+        ] {
+            assert!(matches!(
+                parse_time(s, now, false),
+                Err(Error::BadFilter(_))
+            ));
+        }
+    }
+}
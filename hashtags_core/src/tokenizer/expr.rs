@@ -0,0 +1,191 @@
+use super::Tokenizer;
+use super::{tag_expr, Expr, Filter};
+use super::super::error::Error;
+
+// Grammar, from the lowest precedence to the highest:
+//
+//   or   := and ('|' and)*
+//   and  := not ('&' not)*
+//   not  := '!' not | atom
+//   atom := '(' or ')' | tag
+pub struct ExprTokenizer {
+}
+
+impl ExprTokenizer {
+    pub fn new() -> ExprTokenizer {
+        ExprTokenizer{}
+    }
+}
+
+struct Parser<'a> {
+    q: &'a str,
+    pos: usize,
+}
+
+fn is_operator(c: char) -> bool {
+    c == '&' || c == '|' || c == '!' || c == '(' || c == ')'
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.q[self.pos..];
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        trimmed.chars().next()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr<'a>, Error> {
+        let mut exprs = vec![self.parse_and()?];
+        while let Some('|') = self.peek() {
+            self.pos += 1;
+            exprs.push(self.parse_and()?);
+        }
+        if exprs.len() == 1 {
+            return Ok(exprs.pop().unwrap());
+        }
+        Ok(Expr::Or(exprs))
+    }
+
+    fn parse_and(&mut self) -> Result<Expr<'a>, Error> {
+        let mut exprs = vec![self.parse_not()?];
+        while let Some('&') = self.peek() {
+            self.pos += 1;
+            exprs.push(self.parse_not()?);
+        }
+        if exprs.len() == 1 {
+            return Ok(exprs.pop().unwrap());
+        }
+        Ok(Expr::And(exprs))
+    }
+
+    fn parse_not(&mut self) -> Result<Expr<'a>, Error> {
+        if let Some('!') = self.peek() {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr<'a>, Error> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let expr = self.parse_or()?;
+                if self.peek() != Some(')') {
+                    return Err(Error::BadFilter(format!(
+                        "missing ')' at {} in '{}'",
+                        self.pos, self.q
+                    )));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            Some(c) if !is_operator(c) => {
+                let rest = &self.q[self.pos..];
+                // '!=' compares key-value tags, it doesn't negate.
+                let end = rest
+                    .char_indices()
+                    .find(|&(i, c)| {
+                        c.is_whitespace()
+                            || (is_operator(c) && !(c == '!' && rest[i+1..].starts_with('=')))
+                    })
+                    .map_or(rest.len(), |(i, _)| i);
+                self.pos += end;
+                Ok(tag_expr(&rest[..end]))
+            }
+            Some(c) => Err(Error::BadFilter(format!(
+                "unexpected '{}' at {} in '{}'",
+                c, self.pos, self.q
+            ))),
+            None => Err(Error::BadFilter(format!(
+                "unexpected end of filter '{}'",
+                self.q
+            ))),
+        }
+    }
+}
+
+impl Tokenizer for ExprTokenizer {
+    fn tokenize<'a>(&self, q: &'a str) -> Result<Filter<'a>, Error> {
+        let mut p = Parser { q, pos: 0 };
+        let expr = p.parse_or()?;
+        if let Some(c) = p.peek() {
+            return Err(Error::BadFilter(format!(
+                "unexpected '{}' at {} in '{}'",
+                c, p.pos, q
+            )));
+        }
+        Ok(Filter::new(expr))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::error::Error;
+    use super::super::Expr::{And, Attr, Not, Or, Pattern, Tag};
+    use super::super::Op;
+    use super::ExprTokenizer;
+    use super::Tokenizer;
+
+    #[test]
+    fn test_basic() {
+        let t = ExprTokenizer::new();
+        let f = t.tokenize("a").unwrap();
+        assert_eq!(f.expr, Tag("a"));
+        let f = t.tokenize("a & b | c & !d").unwrap();
+        assert_eq!(
+            f.expr,
+            Or(vec![
+                And(vec![Tag("a"), Tag("b")]),
+                And(vec![Tag("c"), Not(Box::new(Tag("d")))])
+            ])
+        );
+        let f = t.tokenize("(work&rust) | (home & !chores)").unwrap();
+        assert_eq!(
+            f.expr,
+            Or(vec![
+                And(vec![Tag("work"), Tag("rust")]),
+                And(vec![Tag("home"), Not(Box::new(Tag("chores")))])
+            ])
+        );
+        let f = t.tokenize("!(a | !(b & c))").unwrap();
+        assert_eq!(
+            f.expr,
+            Not(Box::new(Or(vec![
+                Tag("a"),
+                Not(Box::new(And(vec![Tag("b"), Tag("c")])))
+            ])))
+        );
+        let f = t.tokenize("proj-* & !*2018").unwrap();
+        assert_eq!(f.expr, And(vec![Pattern("proj-*"), Not(Box::new(Pattern("*2018")))]));
+        let f = t.tokenize("due>=2026-11-01 & !(priority=low | status!=open)").unwrap();
+        assert_eq!(
+            f.expr,
+            And(vec![
+                Attr("due", Op::Ge, "2026-11-01"),
+                Not(Box::new(Or(vec![
+                    Attr("priority", Op::Eq, "low"),
+                    Attr("status", Op::Ne, "open")
+                ])))
+            ])
+        );
+        assert!(matches!(t.tokenize(""), Err(Error::BadFilter(_))));
+        assert!(matches!(t.tokenize("a &"), Err(Error::BadFilter(_))));
+        assert!(matches!(t.tokenize("(a | b"), Err(Error::BadFilter(_))));
+        assert!(matches!(t.tokenize("a b"), Err(Error::BadFilter(_))));
+        assert!(matches!(t.tokenize("a | b)"), Err(Error::BadFilter(_))));
+    }
+
+    #[test]
+    fn test_utf8() {
+        let t = ExprTokenizer::new();
+        let f = t.tokenize("(台積電 | 聯電) & !ETF").unwrap();
+        assert_eq!(
+            f.expr,
+            And(vec![
+                Or(vec![Tag("台積電"), Tag("聯電")]),
+                Not(Box::new(Tag("ETF")))
+            ])
+        );
+    }
+}
@@ -1,11 +1,112 @@
+pub mod expr;
 pub mod simple;
 
+use std::boxed::Box;
 use std::result::Result;
 use super::error::Error;
+use super::model::{Cursor, NoteOrder, TimeRange};
+
+#[derive(Debug, PartialEq)]
+pub enum Expr<'a> {
+    Tag(&'a str),
+    // Tags matching a glob pattern, ex. 'proj-*' or '*2018'.
+    Pattern(&'a str),
+    // Comparison of the values of a key-value tag, ex. 'due<2026-11-01'.
+    Attr(&'a str, Op, &'a str),
+    And(Vec<Expr<'a>>),
+    Or(Vec<Expr<'a>>),
+    Not(Box<Expr<'a>>),
+}
+
+// Values are compared as text, which orders dates in ISO 8601 as well.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    pub fn sql(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+}
+
+// Longer operators go first, 'key:value' is the same as 'key=value'.
+const OPS: [(&str, Op); 7] = [
+    ("<=", Op::Le),
+    (">=", Op::Ge),
+    ("!=", Op::Ne),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+    ("=", Op::Eq),
+    (":", Op::Eq),
+];
 
 pub struct Filter<'a> {
-    pub ands: Vec<&'a str>,
-    pub ors: Vec<&'a str>,
+    // An empty conjunction matches all notes.
+    pub expr: Expr<'a>,
+    // Notes matching any of these are excluded from the result.
+    pub excludes: Vec<Expr<'a>>,
+    // Full-text search terms on note contents, in FTS5 query syntax.
+    pub text: Option<&'a str>,
+    pub created: TimeRange,
+    // Notes never updated are bounded by their creation time.
+    pub updated: TimeRange,
+    // Notes are ordered by relevance when searching by text, otherwise by
+    // creation time from the newest.
+    pub order: Option<NoteOrder>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    // Only notes after this position are returned, which requires ordering
+    // by creation time.
+    pub after: Option<Cursor>,
+    // Notes in trash are included as well.
+    pub trashed: bool,
+}
+
+impl<'a> Filter<'a> {
+    pub fn new(expr: Expr<'a>) -> Filter<'a> {
+        Filter {
+            expr,
+            excludes: vec![],
+            text: None,
+            created: TimeRange::default(),
+            updated: TimeRange::default(),
+            order: None,
+            limit: None,
+            offset: None,
+            after: None,
+            trashed: false,
+        }
+    }
+}
+
+// Tags with '*' are taken as patterns, and those with an operator as
+// comparisons of key-value tags.
+pub fn tag_expr<'a>(tag: &'a str) -> Expr<'a> {
+    if let Some(i) = tag.find(|c| "<>=!:".contains(c)) {
+        for (s, op) in OPS.iter() {
+            if i > 0 && tag[i..].starts_with(s) {
+                return Expr::Attr(&tag[..i], *op, &tag[i+s.len()..]);
+            }
+        }
+    }
+    if tag.contains('*') {
+        Expr::Pattern(tag)
+    } else {
+        Expr::Tag(tag)
+    }
 }
 
 pub trait Tokenizer {
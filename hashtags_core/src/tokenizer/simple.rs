@@ -1,5 +1,6 @@
+use std::boxed::Box;
 use super::Tokenizer;
-use super::Filter;
+use super::{tag_expr, Expr, Filter};
 use super::super::error::Error;
 
 pub struct SimpleTokenizer {
@@ -11,28 +12,57 @@ impl SimpleTokenizer {
     }
 }
 
+// Tags prefixed with '-' or '!' are exclusions, returns the tag excluded.
+fn excluded(tag: &str) -> Result<Option<&str>, Error> {
+    if !tag.starts_with(&['-', '!'][..]) {
+        return Ok(None);
+    }
+    match &tag[1..] {
+        "" => Err(Error::BadFilter(format!("nothing to exclude: '{}'", tag))),
+        t => Ok(Some(t)),
+    }
+}
+
 impl Tokenizer for SimpleTokenizer {
     fn tokenize<'a>(&self, q: &'a str) -> Result<Filter<'a>, Error> {
-        let mut ands: Vec<&str> = q.split(",").collect();
+        let mut ands: Vec<&str> = q.split(',').collect();
         let mut ors = Vec::<&str>::new();
-        match ands.last() {
-            Some(&i) => {
-                if i.contains("|") {
-                    ors = i.split("|").collect();
-                    ands.pop();
-                }
-            },
-            None => (),
+        if let Some(&i) = ands.last() {
+            if i.contains('|') {
+                ors = i.split('|').collect();
+                ands.pop();
+            }
         }
-        Ok(Filter{
-            ands,
-            ors,
-        })
+        let mut excludes = Vec::<Expr>::new();
+        let mut exprs = Vec::<Expr>::new();
+        for a in ands {
+            match excluded(a)? {
+                Some(e) => excludes.push(tag_expr(e)),
+                None => exprs.push(tag_expr(a)),
+            }
+        }
+        if !ors.is_empty() {
+            // An exclusion as an alternative stands for notes without the tag.
+            let mut alts = Vec::<Expr>::new();
+            for o in ors {
+                alts.push(match excluded(o)? {
+                    Some(e) => Expr::Not(Box::new(tag_expr(e))),
+                    None => tag_expr(o),
+                });
+            }
+            exprs.push(Expr::Or(alts));
+        }
+        let mut f = Filter::new(Expr::And(exprs));
+        f.excludes = excludes;
+        Ok(f)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::super::super::error::Error;
+    use super::super::Expr::{And, Attr, Not, Or, Pattern, Tag};
+    use super::super::Op;
     use super::SimpleTokenizer;
     use super::Tokenizer;
 
@@ -40,19 +70,55 @@ mod test {
     fn test_basic() {
         let t = SimpleTokenizer::new();
         let f = t.tokenize("a,b,c,d").unwrap();
-        assert_eq!(f.ands, vec!["a", "b", "c", "d"]);
+        assert_eq!(f.expr, And(vec![Tag("a"), Tag("b"), Tag("c"), Tag("d")]));
         let f = t.tokenize("a,b,c|d|e").unwrap();
-        assert_eq!(f.ands, vec!["a", "b"]);
-        assert_eq!(f.ors, vec!["c", "d", "e"]);
+        assert_eq!(
+            f.expr,
+            And(vec![Tag("a"), Tag("b"), Or(vec![Tag("c"), Tag("d"), Tag("e")])])
+        );
+        assert!(f.excludes.is_empty());
+        let f = t.tokenize("a,-b,!c,d|e").unwrap();
+        assert_eq!(f.expr, And(vec![Tag("a"), Or(vec![Tag("d"), Tag("e")])]));
+        assert_eq!(f.excludes, vec![Tag("b"), Tag("c")]);
+        let f = t.tokenize("-done").unwrap();
+        assert_eq!(f.expr, And(vec![]));
+        assert_eq!(f.excludes, vec![Tag("done")]);
+        let f = t.tokenize("proj-*,-*2018,a|*b*").unwrap();
+        assert_eq!(f.expr, And(vec![Pattern("proj-*"), Or(vec![Tag("a"), Pattern("*b*")])]));
+        assert_eq!(f.excludes, vec![Pattern("*2018")]);
+        let f = t.tokenize("due<2026-11-01,priority:high,-status!=done").unwrap();
+        assert_eq!(
+            f.expr,
+            And(vec![Attr("due", Op::Lt, "2026-11-01"), Attr("priority", Op::Eq, "high")])
+        );
+        assert_eq!(f.excludes, vec![Attr("status", Op::Ne, "done")]);
+        let f = t.tokenize("a,b|-c|!d").unwrap();
+        assert_eq!(
+            f.expr,
+            And(vec![Tag("a"), Or(vec![Tag("b"), Not(Box::new(Tag("c"))), Not(Box::new(Tag("d")))])])
+        );
+        assert!(f.excludes.is_empty());
+        for q in &["-", "a,!", "a|-"] {
+            assert!(matches!(t.tokenize(q), Err(Error::BadFilter(_))));
+        }
     }
 
     #[test]
     fn test_utf8() {
         let t = SimpleTokenizer::new();
         let f = t.tokenize("台積電,聯電,ETF").unwrap();
-        assert_eq!(f.ands, vec!["台積電", "聯電", "ETF"]);
+        assert_eq!(f.expr, And(vec![Tag("台積電"), Tag("聯電"), Tag("ETF")]));
         let f = t.tokenize("台積電,聯電,ETF|台達電|華碩").unwrap();
-        assert_eq!(f.ands, vec!["台積電", "聯電"]);
-        assert_eq!(f.ors, vec!["ETF", "台達電", "華碩"]);
+        assert_eq!(
+            f.expr,
+            And(vec![
+                Tag("台積電"),
+                Tag("聯電"),
+                Or(vec![Tag("ETF"), Tag("台達電"), Tag("華碩")])
+            ])
+        );
+        let f = t.tokenize("台積電,-聯電,!ETF").unwrap();
+        assert_eq!(f.expr, And(vec![Tag("台積電")]));
+        assert_eq!(f.excludes, vec![Tag("聯電"), Tag("ETF")]);
     }
 }